@@ -0,0 +1,434 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Sample rate used for playback and recording
+pub const SAMPLE_RATE: i32 = 44100;
+
+/// Time taken to fade the tone in and out, long enough to avoid clicks but short enough to be inaudible.
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+    Noise,
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Waveform::Square
+    }
+}
+
+impl Waveform {
+    const NAMES: [(&str, Waveform); 5] = [
+        ("square", Waveform::Square),
+        ("sine", Waveform::Sine),
+        ("triangle", Waveform::Triangle),
+        ("sawtooth", Waveform::Sawtooth),
+        ("noise", Waveform::Noise),
+    ];
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Waveform::NAMES.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, waveform)| *waveform)
+            .ok_or_else(|| format!(
+                "unknown waveform '{}', expected one of {}",
+                s,
+                Waveform::NAMES.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "),
+            ))
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Waveform::NAMES.iter().find(|(_, w)| w == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+/// The buzzer switching on or off, at a point in emulated time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundEvent {
+    /// Number of frames run before the one the change happened in
+    pub frame: u64,
+    /// How far through the frame the change happened, 0.0 - 1.0
+    pub offset: f32,
+    pub on: bool,
+}
+
+impl SoundEvent {
+    fn time(&self) -> f64 {
+        self.frame as f64 + self.offset as f64
+    }
+}
+
+/// Most emulated time that queued sound may run ahead of playback before the schedule is reset,
+/// e.g. after the emulator was paused or the audio device stalled.
+const MAX_LEAD_FRAMES: f64 = 4.0;
+
+/// Turns timestamped sound events into a gate for each audio sample, so beeps last exactly as long
+/// as the sound timer held them on regardless of when frames are presented.
+///
+/// Emulated time is mapped to sample time on the first event, delayed by a fixed latency to give
+/// the emulator time to deliver events before they are due. Events arriving too late to be played
+/// on time, or too far ahead, re-anchor the mapping.
+pub struct SoundScheduler {
+    samples_per_frame: f64,
+    latency: f64,
+    /// Sample number at which emulated time 0 plays
+    anchor: Option<f64>,
+    /// Samples generated so far
+    position: u64,
+    pending: VecDeque<(u64, bool)>,
+    gate: bool,
+}
+
+impl SoundScheduler {
+    pub fn new(sample_rate: i32, frame_rate: u32, latency_samples: usize) -> Self {
+        SoundScheduler {
+            samples_per_frame: sample_rate as f64 / frame_rate as f64,
+            latency: latency_samples as f64,
+            anchor: None,
+            position: 0,
+            pending: VecDeque::new(),
+            gate: false,
+        }
+    }
+
+    pub fn push(&mut self, event: SoundEvent) {
+        let time = event.time() * self.samples_per_frame;
+        let now = self.position as f64;
+
+        let anchor = match self.anchor {
+            Some(anchor) if anchor + time >= now && anchor + time <= now + self.latency + MAX_LEAD_FRAMES * self.samples_per_frame => anchor,
+            _ => now + self.latency - time,
+        };
+        self.anchor = Some(anchor);
+
+        // Never reorder events, even if the schedule was re-anchored earlier
+        let mut sample = (anchor + time).round() as u64;
+        if let Some((last, _)) = self.pending.back() {
+            sample = sample.max(*last);
+        }
+        self.pending.push_back((sample, event.on));
+    }
+
+    /// Whether the buzzer is on for the next sample
+    pub fn next_gate(&mut self) -> bool {
+        while let Some((sample, on)) = self.pending.front() {
+            if *sample > self.position {
+                break;
+            }
+            self.gate = *on;
+            self.pending.pop_front();
+        }
+
+        self.position += 1;
+        self.gate
+    }
+}
+
+/// Digitised sound waiting to be played, e.g. by an audio device. It's played as soon as it
+/// arrives, and if it arrives faster than it plays the oldest is dropped, so it can never fall
+/// more than a few frames behind the emulator.
+pub struct SampleQueue {
+    samples: VecDeque<f32>,
+    limit: usize,
+}
+
+impl SampleQueue {
+    pub fn new(sample_rate: i32, frame_rate: u32) -> Self {
+        let limit = (MAX_LEAD_FRAMES * sample_rate as f64 / frame_rate as f64) as usize;
+        SampleQueue { samples: VecDeque::with_capacity(limit), limit }
+    }
+
+    /// Queue samples, making room first so the queue never grows past the space it started with
+    pub fn extend(&mut self, samples: &[f32]) {
+        let samples = &samples[samples.len().saturating_sub(self.limit)..];
+        let excess = (self.samples.len() + samples.len()).saturating_sub(self.limit);
+        self.samples.drain(..excess);
+        self.samples.extend(samples);
+    }
+
+    /// The next sample, silence once the queue runs dry
+    pub fn next_sample(&mut self) -> f32 {
+        self.samples.pop_front().unwrap_or(0.0)
+    }
+}
+
+/// Something that plays the buzzer, e.g. an audio device.
+pub trait AudioSink {
+    fn set_tone(&mut self, tone: Tone);
+
+    fn set_muted(&mut self, muted: bool);
+
+    /// Play the sound events from a frame along with any digitised sound the machine played
+    /// during it, a frame's worth of samples at `SAMPLE_RATE` mixed over the buzzer. This should
+    /// be called once per frame.
+    fn play(&mut self, events: &[SoundEvent], samples: &[f32]) -> io::Result<()>;
+
+    /// Called once the emulator stops, to flush anything still buffered
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays nothing, for machines without sound.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_tone(&mut self, _tone: Tone) {}
+
+    fn set_muted(&mut self, _muted: bool) {}
+
+    fn play(&mut self, _events: &[SoundEvent], _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where to send sound.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioBackend {
+    /// The default audio device, or nothing if it can't be opened
+    Sdl,
+    Null,
+    /// A WAV file, or raw samples for any other extension
+    File(PathBuf),
+}
+
+impl Default for AudioBackend {
+    fn default() -> Self {
+        AudioBackend::Sdl
+    }
+}
+
+impl FromStr for AudioBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "sdl" => Ok(AudioBackend::Sdl),
+            _ if s == "null" => Ok(AudioBackend::Null),
+            Some(("file", path)) if !path.is_empty() => Ok(AudioBackend::File(PathBuf::from(path))),
+            _ => Err(format!("unknown audio backend '{}', expected sdl, null or file:<path>", s)),
+        }
+    }
+}
+
+/// What the buzzer sounds like.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    /// Pitch in Hz
+    pub frequency: f32,
+    /// Peak amplitude, 0.0 - 1.0
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Generates the buzzer tone, fading it in and out as it is switched on and off.
+pub struct Synth {
+    tone: Tone,
+    sample_rate: f32,
+    phase: f32,
+    envelope: f32,
+    envelope_step: f32,
+    noise: u16,
+    noise_level: f32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone, sample_rate: i32) -> Self {
+        let sample_rate = sample_rate as f32;
+        Synth {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            envelope: 0.0,
+            envelope_step: 1.0 / (ENVELOPE_SECONDS * sample_rate),
+            noise: 0xACE1,
+            noise_level: 1.0,
+        }
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /// Fill a buffer with samples, fading towards silence when the gate is off.
+    pub fn fill(&mut self, out: &mut [f32], gate: bool) {
+        out.iter_mut().for_each(|x| *x = self.next_sample(gate));
+    }
+
+    pub fn next_sample(&mut self, gate: bool) -> f32 {
+        self.envelope = if gate {
+            (self.envelope + self.envelope_step).min(1.0)
+        } else {
+            (self.envelope - self.envelope_step).max(0.0)
+        };
+
+        // Let the oscillator stop while silent so every beep starts at the same phase
+        if self.envelope == 0.0 {
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let sample = self.oscillate();
+        sample * self.tone.volume * self.envelope
+    }
+
+    fn oscillate(&mut self) -> f32 {
+        let phase = self.phase;
+        let next = phase + self.tone.frequency / self.sample_rate;
+        self.phase = next % 1.0;
+
+        match self.tone.waveform {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Noise => {
+                // Clock a 16 bit LFSR once per period so the frequency still sets the pitch of the noise
+                if next >= 1.0 || phase == 0.0 {
+                    let bit = (self.noise ^ (self.noise >> 2) ^ (self.noise >> 3) ^ (self.noise >> 5)) & 1;
+                    self.noise = (self.noise >> 1) | (bit << 15);
+                    self.noise_level = if self.noise & 1 == 1 { 1.0 } else { -1.0 };
+                }
+                self.noise_level
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(waveform: Waveform) -> Tone {
+        Tone { waveform, frequency: 1000.0, volume: 0.5 }
+    }
+
+    #[test]
+    fn it_parses_waveforms() {
+        assert_eq!("Sine".parse(), Ok(Waveform::Sine));
+        assert_eq!(Waveform::Sawtooth.to_string().parse(), Ok(Waveform::Sawtooth));
+        assert!("organ".parse::<Waveform>().is_err());
+    }
+
+    #[test]
+    fn it_parses_audio_backends() {
+        assert_eq!("null".parse(), Ok(AudioBackend::Null));
+        assert_eq!("file:out.wav".parse(), Ok(AudioBackend::File(PathBuf::from("out.wav"))));
+        assert!("file:".parse::<AudioBackend>().is_err());
+        assert!("pulse".parse::<AudioBackend>().is_err());
+    }
+
+    #[test]
+    fn it_fades_in_and_out() {
+        let mut synth = Synth::new(tone(Waveform::Square), 8000);
+        let mut buffer = [0.0; 80];
+
+        // The attack takes 5ms, i.e. 40 samples
+        synth.fill(&mut buffer, true);
+        assert!(buffer[0].abs() < 0.05);
+        assert!(buffer[1].abs() < buffer[2].abs());
+        assert_eq!(buffer[79].abs(), 0.5);
+
+        // And so does the release
+        synth.fill(&mut buffer, false);
+        assert!(buffer[0].abs() > 0.45);
+        buffer[40..].iter().for_each(|x| assert_eq!(*x, 0.0));
+    }
+
+    fn event(frame: u64, offset: f32, on: bool) -> SoundEvent {
+        SoundEvent { frame, offset, on }
+    }
+
+    #[test]
+    fn it_schedules_sound_events() {
+        // 100 samples per frame, with 50 samples latency
+        let mut scheduler = SoundScheduler::new(6000, 60, 50);
+        scheduler.push(event(10, 0.5, true));
+        scheduler.push(event(12, 0.0, false));
+
+        let gates: Vec<bool> = (0..300).map(|_| scheduler.next_gate()).collect();
+        assert!(!gates[49]);
+        assert!(gates[50..200].iter().all(|g| *g));
+        assert!(!gates[200]);
+    }
+
+    #[test]
+    fn it_reschedules_late_sound_events() {
+        let mut scheduler = SoundScheduler::new(6000, 60, 50);
+        scheduler.push(event(0, 0.0, true));
+        scheduler.push(event(1, 0.0, false));
+        (0..1000).for_each(|_| { scheduler.next_gate(); });
+
+        // Frame 2 should have played at sample 250, so it's moved to play after the latency instead,
+        // and the events after it keep to the new schedule
+        scheduler.push(event(2, 0.0, true));
+        scheduler.push(event(2, 0.5, false));
+        let gates: Vec<bool> = (0..200).map(|_| scheduler.next_gate()).collect();
+        assert!(!gates[49]);
+        assert!(gates[50..100].iter().all(|g| *g));
+        assert!(!gates[100]);
+    }
+
+    #[test]
+    fn it_queues_digitised_sound() {
+        // 100 samples per frame, so 400 can be queued
+        let mut queue = SampleQueue::new(6000, 60);
+        queue.extend(&[0.5; 300]);
+        queue.extend(&[-0.5; 200]);
+
+        // The oldest 100 samples are dropped to make room
+        let samples: Vec<f32> = (0..500).map(|_| queue.next_sample()).collect();
+        assert!(samples[..200].iter().all(|x| *x == 0.5));
+        assert!(samples[200..400].iter().all(|x| *x == -0.5));
+        assert!(samples[400..].iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn it_generates_waveforms() {
+        // 8 samples per period, taken once the envelope is fully open
+        let period = |waveform| {
+            let mut synth = Synth::new(tone(waveform), 8000);
+            let mut buffer = [0.0; 48];
+            synth.fill(&mut buffer, true);
+            buffer[40..].to_vec()
+        };
+
+        assert_eq!(period(Waveform::Square), vec![0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+        assert_eq!(period(Waveform::Sawtooth), vec![-0.5, -0.375, -0.25, -0.125, 0.0, 0.125, 0.25, 0.375]);
+        assert_eq!(period(Waveform::Triangle), vec![-0.5, -0.25, 0.0, 0.25, 0.5, 0.25, 0.0, -0.25]);
+
+        let sine = period(Waveform::Sine);
+        assert!(sine[0].abs() < 1e-6);
+        assert!((sine[2] - 0.5).abs() < 1e-6);
+
+        // Noise holds each level for a whole period
+        let noise = period(Waveform::Noise);
+        noise.iter().for_each(|x| assert_eq!(x.abs(), 0.5));
+    }
+}
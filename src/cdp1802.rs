@@ -0,0 +1,426 @@
+/// Everything the CPU is connected to: memory, the I/O ports and the external flag lines.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+
+    /// OUT 1-7 puts a byte from memory on the data bus for a device
+    fn output(&mut self, port: u8, value: u8);
+
+    /// INP 1-7 reads a byte from a device into D and memory
+    fn input(&mut self, port: u8) -> u8;
+
+    /// Whether external flag EF1-EF4 (`flag` 1-4) is asserted
+    fn flag(&mut self, flag: u8) -> bool;
+}
+
+/// An RCA CDP1802 COSMAC CPU.
+#[derive(Clone, Debug, Default)]
+pub struct Cdp1802 {
+    /// The 16 general purpose scratchpad registers, any of which can be the program counter
+    pub r: [u16; 16],
+    /// The accumulator
+    pub d: u8,
+    /// Carry / not borrow
+    pub df: bool,
+    /// Which register is the program counter
+    pub p: u8,
+    /// Which register addresses memory for ALU and stack instructions
+    pub x: u8,
+    /// X and P saved by an interrupt or MARK
+    pub t: u8,
+    /// Interrupt enable
+    pub ie: bool,
+    /// The Q output flip flop
+    pub q: bool,
+    /// Waiting in IDL for an interrupt or DMA
+    idle: bool,
+}
+
+impl Cdp1802 {
+    /// The state after a reset: R0 is the program counter and interrupts are enabled
+    pub fn new() -> Self {
+        Cdp1802 { ie: true, ..Cdp1802::default() }
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let pc = self.p as usize;
+        let value = bus.read(self.r[pc]);
+        self.r[pc] = self.r[pc].wrapping_add(1);
+        value
+    }
+
+    /// M(R(X))
+    fn mx<B: Bus>(&self, bus: &mut B) -> u8 {
+        bus.read(self.r[self.x as usize])
+    }
+
+    fn set_low(&mut self, n: usize, value: u8) {
+        self.r[n] = self.r[n] & 0xFF00 | value as u16;
+    }
+
+    fn set_high(&mut self, n: usize, value: u8) {
+        self.r[n] = self.r[n] & 0x00FF | (value as u16) << 8;
+    }
+
+    /// D = a + b + carry, with DF the carry out
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// D = a - b - borrow, with DF set if there was no borrow
+    fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+        self.add(a, !b, !borrow);
+    }
+
+    /// Branch within the current page to the byte following the opcode, or skip over it
+    fn short_branch<B: Bus>(&mut self, bus: &mut B, condition: bool) {
+        let pc = self.p as usize;
+        if condition {
+            let target = bus.read(self.r[pc]);
+            self.set_low(pc, target);
+        } else {
+            self.r[pc] = self.r[pc].wrapping_add(1);
+        }
+    }
+
+    /// Branch to the address in the two bytes following the opcode, or skip over them
+    fn long_branch<B: Bus>(&mut self, bus: &mut B, condition: bool) {
+        let pc = self.p as usize;
+        if condition {
+            let high = bus.read(self.r[pc]);
+            let low = bus.read(self.r[pc].wrapping_add(1));
+            self.r[pc] = (high as u16) << 8 | low as u16;
+        } else {
+            self.r[pc] = self.r[pc].wrapping_add(2);
+        }
+    }
+
+    /// Skip the next two bytes
+    fn long_skip(&mut self, condition: bool) {
+        if condition {
+            let pc = self.p as usize;
+            self.r[pc] = self.r[pc].wrapping_add(2);
+        }
+    }
+
+    /// Respond to an interrupt request if interrupts are enabled: save X and P in T, then run the
+    /// handler with R1 as the program counter and R2 as the stack pointer.
+    /// Returns the machine cycles taken.
+    pub fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+
+        self.idle = false;
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        1
+    }
+
+    /// A DMA out cycle: a device reads the byte R0 points to. Returns the byte.
+    pub fn dma_out<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.idle = false;
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        value
+    }
+
+    /// Execute one instruction, returning the machine cycles it took
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(bus);
+        let n = (opcode & 0xF) as usize;
+        let x = self.x as usize;
+
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = bus.read(self.r[n]),
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let condition = match n & 0x7 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    flag => bus.flag(flag as u8 - 3),
+                };
+                // The upper half are the inverted conditions, where 38 (the inverse of BR) is SKP
+                self.short_branch(bus, condition != (n >= 0x8));
+            }
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n], self.d),
+            0x6 => match n {
+                0x0 => self.r[x] = self.r[x].wrapping_add(1),
+                0x1..=0x7 => {
+                    let value = self.mx(bus);
+                    bus.output(n as u8, value);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                }
+                // 68 isn't an instruction on the 1802
+                0x8 => {}
+                _ => {
+                    self.d = bus.input(n as u8 - 8);
+                    bus.write(self.r[x], self.d);
+                }
+            },
+            0x7 => match n {
+                0x0 | 0x1 => {
+                    // RET and DIS
+                    let value = self.mx(bus);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                    self.x = value >> 4;
+                    self.p = value & 0xF;
+                    self.ie = n == 0x0;
+                }
+                0x2 => {
+                    self.d = self.mx(bus);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                }
+                0x3 => {
+                    bus.write(self.r[x], self.d);
+                    self.r[x] = self.r[x].wrapping_sub(1);
+                }
+                0x4 => self.add(self.mx(bus), self.d, self.df),
+                0x5 => self.subtract(self.mx(bus), self.d, !self.df),
+                0x6 => {
+                    let carry = self.df;
+                    self.df = self.d & 1 != 0;
+                    self.d = self.d >> 1 | (carry as u8) << 7;
+                }
+                0x7 => self.subtract(self.d, self.mx(bus), !self.df),
+                0x8 => bus.write(self.r[x], self.t),
+                0x9 => {
+                    // MARK
+                    self.t = self.x << 4 | self.p;
+                    bus.write(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                }
+                0xA => self.q = false,
+                0xB => self.q = true,
+                0xC => {
+                    let value = self.fetch(bus);
+                    self.add(value, self.d, self.df);
+                }
+                0xD => {
+                    let value = self.fetch(bus);
+                    self.subtract(value, self.d, !self.df);
+                }
+                0xE => {
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = self.d << 1 | carry as u8;
+                }
+                _ => {
+                    let value = self.fetch(bus);
+                    self.subtract(self.d, value, !self.df);
+                }
+            },
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.set_low(n, self.d),
+            0xB => self.set_high(n, self.d),
+            0xC => {
+                match n {
+                    0x0 => self.long_branch(bus, true),
+                    0x1 => self.long_branch(bus, self.q),
+                    0x2 => self.long_branch(bus, self.d == 0),
+                    0x3 => self.long_branch(bus, self.df),
+                    0x4 => {}
+                    0x5 => self.long_skip(!self.q),
+                    0x6 => self.long_skip(self.d != 0),
+                    0x7 => self.long_skip(!self.df),
+                    0x8 => self.long_skip(true),
+                    0x9 => self.long_branch(bus, !self.q),
+                    0xA => self.long_branch(bus, self.d != 0),
+                    0xB => self.long_branch(bus, !self.df),
+                    0xC => self.long_skip(self.ie),
+                    0xD => self.long_skip(self.q),
+                    0xE => self.long_skip(self.d == 0),
+                    _ => self.long_skip(self.df),
+                }
+                // Long branches and skips take an extra machine cycle
+                return 3;
+            }
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => {
+                // The ALU instructions, F8-FF take an immediate byte instead of M(R(X))
+                let operand = if n < 0x8 { self.mx(bus) } else { self.fetch(bus) };
+                match n & 0x7 {
+                    0x0 => self.d = operand,
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    0x4 => self.add(operand, self.d, false),
+                    0x5 => self.subtract(operand, self.d, false),
+                    0x6 if n == 0x6 => {
+                        self.df = self.d & 1 != 0;
+                        self.d >>= 1;
+                    }
+                    0x6 => {
+                        // FE is SHL, it only looked like it took an operand
+                        let pc = self.p as usize;
+                        self.r[pc] = self.r[pc].wrapping_sub(1);
+                        self.df = self.d & 0x80 != 0;
+                        self.d <<= 1;
+                    }
+                    _ => self.subtract(self.d, operand, false),
+                }
+            }
+        }
+
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64K of RAM, with a byte that can be read from each port and the last byte written to each
+    struct TestBus {
+        memory: Vec<u8>,
+        inputs: [u8; 8],
+        outputs: [u8; 8],
+        flags: [bool; 5],
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize] = value;
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs[port as usize] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            self.inputs[port as usize]
+        }
+
+        fn flag(&mut self, flag: u8) -> bool {
+            self.flags[flag as usize]
+        }
+    }
+
+    fn bus(program: &[u8]) -> TestBus {
+        let mut bus = TestBus { memory: vec![0; 0x10000], inputs: [0; 8], outputs: [0; 8], flags: [false; 5] };
+        bus.memory[..program.len()].copy_from_slice(program);
+        bus
+    }
+
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, TestBus) {
+        let mut bus = bus(program);
+        let mut cpu = Cdp1802::new();
+        for _ in 0..steps {
+            cpu.step(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn it_loads_and_stores() {
+        // LDI 12, PHI 3, LDI 34, PLO 3, LDI AB, STR 3, LDI 00, LDN 3
+        let (cpu, bus) = run(&[0xF8, 0x12, 0xB3, 0xF8, 0x34, 0xA3, 0xF8, 0xAB, 0x53, 0xF8, 0x00, 0x03], 8);
+        assert_eq!(cpu.r[3], 0x1234);
+        assert_eq!(bus.memory[0x1234], 0xAB);
+        assert_eq!(cpu.d, 0xAB);
+        assert_eq!(cpu.r[0], 12);
+    }
+
+    #[test]
+    fn it_does_arithmetic() {
+        // LDI F0, ADI 20: carry
+        let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+
+        // LDI 10, SMI 20: borrow, so DF is clear
+        let (cpu, _) = run(&[0xF8, 0x10, 0xFF, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false));
+
+        // LDI 10, SDI 20: 20 - 10 without a borrow
+        let (cpu, _) = run(&[0xF8, 0x10, 0xFD, 0x20], 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+
+        // LDI 81, SHL, then SHRC rotates the carry back in
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFE], 2);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+        let (cpu, _) = run(&[0xF8, 0x81, 0xFE, 0x76], 3);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+    }
+
+    #[test]
+    fn it_branches() {
+        // LDI 00, BZ 06, LDI 01, (06) LBNZ 0000, LDI 02
+        let (cpu, _) = run(&[0xF8, 0x00, 0x32, 0x06, 0xF8, 0x01, 0xCA, 0x00, 0x00, 0xF8, 0x02], 4);
+        assert_eq!(cpu.d, 0x02);
+
+        // B3 is taken when EF3 is asserted
+        let mut bus = bus(&[0x36, 0x40]);
+        bus.flags[3] = true;
+        let mut cpu = Cdp1802::new();
+        cpu.step(&mut bus);
+        assert_eq!(cpu.r[0], 0x40);
+
+        // Long skips take three cycles
+        let mut cpu = Cdp1802::new();
+        bus.memory[0x40] = 0xC8;
+        cpu.r[0] = 0x40;
+        assert_eq!(cpu.step(&mut bus), 3);
+        assert_eq!(cpu.r[0], 0x43);
+    }
+
+    #[test]
+    fn it_calls_subroutines_and_handles_interrupts() {
+        let mut bus = bus(&[]);
+        let mut cpu = Cdp1802::new();
+        cpu.r[2] = 0x100;
+        cpu.r[1] = 0x80;
+        cpu.x = 3;
+        cpu.p = 5;
+
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!((cpu.p, cpu.x, cpu.t, cpu.ie), (1, 2, 0x35, false));
+        assert_eq!(cpu.interrupt(), 0);
+
+        // DEC 2, SAV, RET: back where it was, with interrupts enabled again
+        bus.memory[0x80..0x83].copy_from_slice(&[0x22, 0x78, 0x70]);
+        for _ in 0..3 {
+            cpu.step(&mut bus);
+        }
+        assert_eq!((cpu.p, cpu.x, cpu.ie, cpu.r[2]), (5, 3, true, 0x100));
+    }
+
+    #[test]
+    fn it_does_io() {
+        // SEX 3, LDI 20, PLO 3, INP 4 (6C), OUT 4 (64), SEQ
+        let mut bus = bus(&[0xE3, 0xF8, 0x20, 0xA3, 0x6C, 0x64, 0x7B]);
+        bus.inputs[4] = 0x5A;
+        let mut cpu = Cdp1802::new();
+        for _ in 0..6 {
+            cpu.step(&mut bus);
+        }
+
+        assert_eq!(cpu.d, 0x5A);
+        assert_eq!(bus.memory[0x20], 0x5A);
+        assert_eq!(bus.outputs[4], 0x5A);
+        assert_eq!(cpu.r[3], 0x21);
+        assert!(cpu.q);
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use chip8_rs::audio::{AudioBackend, Waveform};
+use chip8_rs::config::{AudioSettings, Settings};
+use chip8_rs::machine::Machine;
+use chip8_rs::palette::Palette;
+use chip8_rs::phosphor::RenderMode;
+use chip8_rs::quirks::Quirks;
+use chip8_rs::timing::Timing;
+
+#[derive(Parser)]
+#[clap(name = "chip8-rs", version, about = "A CHIP-8 emulator")]
+#[clap(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a ROM, this is the default when no subcommand is given
+    Run(Box<RunArgs>),
+    /// Print the disassembly of a ROM
+    Disasm {
+        /// Path to the ROM to disassemble
+        rom: PathBuf,
+    },
+    /// Print what the ROM database knows about a ROM
+    Info {
+        /// Path to the ROM to look up
+        rom: PathBuf,
+
+        /// Directory holding the community CHIP-8 database files
+        #[clap(long, value_name = "DIR")]
+        romdb: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Path to the ROM to run
+    pub rom: Option<PathBuf>,
+
+    /// Config file to use instead of the one in the user config directory
+    #[clap(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Directory holding the community CHIP-8 database files
+    #[clap(long, value_name = "DIR")]
+    pub romdb: Option<PathBuf>,
+
+    /// Directory of ROMs to list in the menu, F2 opens it
+    #[clap(long, value_name = "DIR")]
+    pub rom_dir: Option<PathBuf>,
+
+    /// Size of each CHIP-8 pixel in the window [default: 10]
+    #[clap(long, value_name = "PIXELS")]
+    pub scale: Option<u32>,
+
+    /// Only scale the display by whole numbers when the window is resized
+    #[clap(long)]
+    pub integer_scaling: bool,
+
+    /// Show the frame rate, instructions per second and quirks over the display, F1 toggles it
+    #[clap(long)]
+    pub hud: bool,
+
+    /// Instructions executed per 60 Hz frame [default: 10]
+    #[clap(long, value_name = "N")]
+    pub cycles_per_frame: Option<usize>,
+
+    /// Instructions executed per second, overrides --cycles-per-frame
+    #[clap(long, value_name = "N", conflicts_with = "cycles-per-frame")]
+    pub ips: Option<usize>,
+
+    /// Instruction timing: fixed, or vip for as long as each instruction took on the COSMAC VIP,
+    /// which ignores --cycles-per-frame [default: fixed]
+    #[clap(long, value_name = "TIMING")]
+    pub timing: Option<Timing>,
+
+    /// Run ROMs as CHIP-8 (chip8), its CHIP-8X (chip8x), CHIP-8E (chip8e), HIRES (hires) or
+    /// MegaChip (megachip) variants, or on an emulated COSMAC VIP (vip) with the original
+    /// interpreter, which hybrid ROMs with 1802 machine code need [default: chip8]
+    #[clap(long, value_name = "MACHINE")]
+    pub machine: Option<Machine>,
+
+    /// The original CHIP-8 interpreter for the VIP machine, a 512 byte image of it
+    #[clap(long, value_name = "PATH")]
+    pub vip_interpreter: Option<PathBuf>,
+
+    /// A quirk preset (vip, schip, modern) and/or quirks to toggle, e.g. "vip,-memory" [default: modern]
+    #[clap(long, value_name = "QUIRKS")]
+    pub quirks: Option<Quirks>,
+
+    /// A preset (white, amber, green, lcd) or FOREGROUND,BACKGROUND as RRGGBB [default: white]
+    #[clap(long, value_name = "PALETTE")]
+    pub palette: Option<Palette>,
+
+    /// Flicker reduction: direct, decay:<0.0-1.0> or blend:<frames> [default: direct]
+    #[clap(long, value_name = "MODE")]
+    pub phosphor: Option<RenderMode>,
+
+    /// Seed the random number generator for reproducible runs
+    #[clap(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Disable sound
+    #[clap(long)]
+    pub mute: bool,
+
+    /// Pitch of the buzzer in Hz [default: 440]
+    #[clap(long, value_name = "HZ")]
+    pub frequency: Option<f32>,
+
+    /// Buzzer waveform: square, sine, triangle, sawtooth or noise [default: square]
+    #[clap(long, value_name = "WAVEFORM")]
+    pub waveform: Option<Waveform>,
+
+    /// Buzzer volume from 0.0 to 1.0 [default: 0.25]
+    #[clap(long, value_name = "VOLUME")]
+    pub volume: Option<f32>,
+
+    /// Where to play sound: sdl, null or file:<PATH> to write a WAV or raw file [default: sdl]
+    #[clap(long, value_name = "BACKEND")]
+    pub audio_backend: Option<AudioBackend>,
+
+    /// Record the sound to a WAV file, in emulated time
+    #[clap(long, value_name = "PATH")]
+    pub record_audio: Option<PathBuf>,
+
+    /// Record the keypad to a movie file, with what's needed to replay the session exactly
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["play-movie", "watch", "watch-source"])]
+    pub record_movie: Option<PathBuf>,
+
+    /// Play back a movie file recorded with --record-movie
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["watch", "watch-source"])]
+    pub play_movie: Option<PathBuf>,
+
+    /// Reset and reload the ROM whenever it changes on disk, keeping the settings and window
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Source file to watch as well as the ROM, --build-command rebuilds the ROM when it changes
+    #[clap(long, value_name = "PATH", requires = "build-command")]
+    pub watch_source: Option<PathBuf>,
+
+    /// Command run through the shell to build the ROM, e.g. "octo game.8o game.ch8"
+    #[clap(long, value_name = "COMMAND")]
+    pub build_command: Option<String>,
+
+    /// Start in fullscreen
+    #[clap(long)]
+    pub fullscreen: bool,
+
+    /// Pace frames by sleeping even when vsync is available
+    #[clap(long)]
+    pub no_vsync: bool,
+
+    /// Run without a window and print the display to stdout when done
+    #[clap(long)]
+    pub headless: bool,
+
+    /// Number of frames to run for in headless mode, or the length of the movie when playing one
+    #[clap(long, value_name = "N", default_value = "600")]
+    pub frames: u64,
+}
+
+impl RunArgs {
+    /// The settings given on the command line, these override the config file
+    pub fn settings(&self) -> Settings {
+        let cycles_per_frame = match self.ips {
+            Some(ips) => Some((ips / chip8_rs::timing::FRAME_RATE as usize).max(1)),
+            None => self.cycles_per_frame,
+        };
+
+        Settings {
+            scale: self.scale,
+            integer_scaling: if self.integer_scaling { Some(true) } else { None },
+            hud: if self.hud { Some(true) } else { None },
+            cycles_per_frame,
+            timing: self.timing,
+            machine: self.machine,
+            quirks: self.quirks,
+            palette: self.palette,
+            phosphor: self.phosphor,
+            keys: Default::default(),
+            keys2: Default::default(),
+            audio: AudioSettings {
+                mute: if self.mute { Some(true) } else { None },
+                frequency: self.frequency,
+                waveform: self.waveform,
+                volume: self.volume,
+                backend: self.audio_backend.clone(),
+            },
+        }
+    }
+}
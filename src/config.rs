@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+use crate::audio::{AudioBackend, Tone, Waveform};
+use crate::machine::Machine;
+use crate::palette::Palette;
+use crate::phosphor::RenderMode;
+use crate::quirks::Quirks;
+use crate::timing::Timing;
+
+/// Parse an optional value from its string form, e.g. `quirks = "vip,-memory"`.
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where D: Deserializer<'de>, T: FromStr, T::Err: Display {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    pub mute: Option<bool>,
+    /// Pitch of the buzzer in Hz
+    pub frequency: Option<f32>,
+    #[serde(deserialize_with = "from_str")]
+    pub waveform: Option<Waveform>,
+    /// 0.0 - 1.0
+    pub volume: Option<f32>,
+    #[serde(deserialize_with = "from_str")]
+    pub backend: Option<AudioBackend>,
+}
+
+impl AudioSettings {
+    fn merge(&self, other: &AudioSettings) -> AudioSettings {
+        AudioSettings {
+            mute: other.mute.or(self.mute),
+            frequency: other.frequency.or(self.frequency),
+            waveform: other.waveform.or(self.waveform),
+            volume: other.volume.or(self.volume),
+            backend: other.backend.clone().or_else(|| self.backend.clone()),
+        }
+    }
+
+    /// The buzzer tone, with anything unset left at its default
+    pub fn tone(&self) -> Tone {
+        let default = Tone::default();
+        Tone {
+            waveform: self.waveform.unwrap_or(default.waveform),
+            frequency: self.frequency.unwrap_or(default.frequency).max(1.0),
+            volume: self.volume.unwrap_or(default.volume).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Settings which can come from the config file, a per-ROM section of it or the command line.
+/// Anything left unset falls back to the next source down, and finally to a built-in default.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub scale: Option<u32>,
+    pub integer_scaling: Option<bool>,
+    /// Show the FPS, IPS and quirks over the display
+    pub hud: Option<bool>,
+    pub cycles_per_frame: Option<usize>,
+    /// Whether instructions take a fixed time or as long as on the COSMAC VIP
+    #[serde(deserialize_with = "from_str")]
+    pub timing: Option<Timing>,
+    /// Whether ROMs run on this interpreter or an emulated COSMAC VIP
+    #[serde(deserialize_with = "from_str")]
+    pub machine: Option<Machine>,
+    #[serde(deserialize_with = "from_str")]
+    pub quirks: Option<Quirks>,
+    #[serde(deserialize_with = "from_str")]
+    pub palette: Option<Palette>,
+    #[serde(deserialize_with = "from_str")]
+    pub phosphor: Option<RenderMode>,
+    /// CHIP-8 key (0-F) to keyboard key name
+    pub keys: BTreeMap<String, String>,
+    /// CHIP-8 key (0-F) on CHIP-8X's second keypad to keyboard key name
+    pub keys2: BTreeMap<String, String>,
+    pub audio: AudioSettings,
+}
+
+impl Settings {
+    /// Combine two sets of settings, values set in `other` take precedence.
+    pub fn merge(&self, other: &Settings) -> Settings {
+        let mut keys = self.keys.clone();
+        keys.extend(other.keys.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let mut keys2 = self.keys2.clone();
+        keys2.extend(other.keys2.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        Settings {
+            scale: other.scale.or(self.scale),
+            integer_scaling: other.integer_scaling.or(self.integer_scaling),
+            hud: other.hud.or(self.hud),
+            cycles_per_frame: other.cycles_per_frame.or(self.cycles_per_frame),
+            timing: other.timing.or(self.timing),
+            machine: other.machine.or(self.machine),
+            quirks: other.quirks.or(self.quirks),
+            palette: other.palette.or(self.palette),
+            phosphor: other.phosphor.or(self.phosphor),
+            keys,
+            keys2,
+            audio: self.audio.merge(&other.audio),
+        }
+    }
+
+    /// The key bindings as (CHIP-8 key, keyboard key name) pairs.
+    pub fn key_bindings(&self) -> Result<Vec<(usize, &str)>, String> {
+        parse_key_bindings(&self.keys)
+    }
+
+    /// The second keypad's key bindings as (CHIP-8 key, keyboard key name) pairs.
+    pub fn second_key_bindings(&self) -> Result<Vec<(usize, &str)>, String> {
+        parse_key_bindings(&self.keys2)
+    }
+}
+
+fn parse_key_bindings(keys: &BTreeMap<String, String>) -> Result<Vec<(usize, &str)>, String> {
+    keys.iter()
+        .map(|(key, name)| match usize::from_str_radix(key, 16) {
+            Ok(k) if k < 16 => Ok((k, name.as_str())),
+            _ => Err(format!("invalid CHIP-8 key '{}' in key bindings, expected 0-F", key)),
+        })
+        .collect()
+}
+
+/// The contents of the configuration file.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Settings,
+    /// Directory holding the community CHIP-8 database files
+    pub romdb: Option<PathBuf>,
+    /// Directory of ROMs to list in the menu
+    pub rom_dir: Option<PathBuf>,
+    /// The original CHIP-8 interpreter for the VIP machine, the 512 bytes it loads at 0000
+    pub vip_interpreter: Option<PathBuf>,
+    /// Per-ROM settings keyed by the SHA-1 hash of the ROM
+    pub roms: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8-rs/config.toml` or the platform equivalent
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8-rs").join("config.toml"))
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// Load the config file, a missing file is the same as an empty one.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Config::parse(&contents)
+                .map_err(|e| format!("invalid config file '{}': {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("could not read config file '{}': {}", path.display(), e)),
+        }
+    }
+
+    /// The settings for a ROM, with its own section applied over the defaults.
+    pub fn settings_for(&self, rom_hash: &str) -> Settings {
+        let rom = self.roms.iter()
+            .find(|(hash, _)| hash.eq_ignore_ascii_case(rom_hash))
+            .map(|(_, settings)| settings);
+
+        match rom {
+            Some(settings) => self.defaults.merge(settings),
+            None => self.defaults.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        scale = 15
+        cycles_per_frame = 12
+        palette = "amber"
+
+        [keys]
+        0 = "Space"
+        A = "Return"
+
+        [keys2]
+        1 = "Keypad 1"
+
+        [audio]
+        mute = true
+        waveform = "triangle"
+        volume = 1.5
+        backend = "null"
+
+        [roms.0123456789ABCDEF0123456789ABCDEF01234567]
+        quirks = "vip"
+        timing = "vip"
+        machine = "vip"
+        cycles_per_frame = 20
+
+        [roms.0123456789ABCDEF0123456789ABCDEF01234567.keys]
+        A = "Z"
+    "#;
+
+    #[test]
+    fn it_parses_a_config_file() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.defaults.scale, Some(15));
+        assert_eq!(config.defaults.cycles_per_frame, Some(12));
+        assert_eq!(config.defaults.palette, Some(Palette::AMBER));
+        assert_eq!(config.defaults.quirks, None);
+        assert_eq!(config.defaults.audio.mute, Some(true));
+        assert_eq!(config.defaults.audio.tone(), Tone { waveform: Waveform::Triangle, frequency: 440.0, volume: 1.0 });
+        assert_eq!(config.defaults.audio.backend, Some(AudioBackend::Null));
+        assert_eq!(config.defaults.key_bindings(), Ok(vec![(0x0, "Space"), (0xA, "Return")]));
+        assert_eq!(config.defaults.second_key_bindings(), Ok(vec![(0x1, "Keypad 1")]));
+        assert_eq!(config.romdb, None);
+        assert_eq!(config.roms.len(), 1);
+    }
+
+    #[test]
+    fn it_reports_invalid_values() {
+        assert!(Config::parse("quirks = \"warp\"").is_err());
+        assert!(Config::parse("scale = \"big\"").is_err());
+        assert!(Config::parse("[audio]\nwaveform = \"organ\"").is_err());
+        assert!(Config::parse("timing = \"pal\"").is_err());
+        assert!(Config::parse("machine = \"eti660\"").is_err());
+
+        // Misspelt settings are reported rather than ignored, wherever they are
+        assert!(Config::parse("cycles_per_fram = 12").is_err());
+        assert!(Config::parse("[audio]\nvolum = 0.5").is_err());
+        assert!(Config::parse("[roms.0123456789ABCDEF0123456789ABCDEF01234567]\nquirk = \"vip\"").is_err());
+
+        let config = Config::parse("[keys]\nG = \"Space\"").unwrap();
+        assert!(config.defaults.key_bindings().is_err());
+        let config = Config::parse("[keys2]\n10 = \"Space\"").unwrap();
+        assert!(config.defaults.second_key_bindings().is_err());
+    }
+
+    #[test]
+    fn it_applies_per_rom_settings() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let settings = config.settings_for("0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(settings.scale, Some(15));
+        assert_eq!(settings.cycles_per_frame, Some(20));
+        assert_eq!(settings.quirks, Some(Quirks::VIP));
+        assert_eq!(settings.timing, Some(Timing::Vip));
+        assert_eq!(settings.machine, Some(Machine::Vip));
+        assert_eq!(settings.key_bindings(), Ok(vec![(0x0, "Space"), (0xA, "Z")]));
+
+        let settings = config.settings_for("ffffffffffffffffffffffffffffffffffffffff");
+        assert_eq!(settings, config.defaults);
+    }
+
+    #[test]
+    fn it_lets_later_settings_override_earlier_ones() {
+        let file = Config::parse(CONFIG).unwrap().defaults;
+        let cli = Settings { scale: Some(5), keys: vec![("0".to_string(), "X".to_string())].into_iter().collect(), ..Settings::default() };
+
+        let settings = file.merge(&cli);
+        assert_eq!(settings.scale, Some(5));
+        assert_eq!(settings.cycles_per_frame, Some(12));
+        assert_eq!(settings.key_bindings(), Ok(vec![(0x0, "X"), (0xA, "Return")]));
+    }
+}
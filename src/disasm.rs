@@ -0,0 +1,91 @@
+/// Describe an opcode in the usual CHIP-8 assembly mnemonics.
+pub fn mnemonic(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let nn = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, _, _, _) => format!("SYS {:#05X}", nnn),
+        (0x1, _, _, _) => format!("JP {:#05X}", nnn),
+        (0x2, _, _, _) => format!("CALL {:#05X}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, {:#04X}", x, nn),
+        (0x4, _, _, _) => format!("SNE V{:X}, {:#04X}", x, nn),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, {:#04X}", x, nn),
+        (0x7, _, _, _) => format!("ADD V{:X}, {:#04X}", x, nn),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, {:#05X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, {:#05X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, {:#04X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        _ => format!("DW {:#06X}", opcode),
+    }
+}
+
+/// Disassemble a ROM loaded at `start`, one line per instruction.
+pub fn disassemble(rom: &[u8], start: u16) -> Vec<String> {
+    rom.chunks(2)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let address = start as usize + i * 2;
+            match bytes {
+                [hi, lo] => {
+                    let opcode = ((*hi as u16) << 8) | *lo as u16;
+                    format!("{:04X}: {:04X}  {}", address, opcode, mnemonic(opcode))
+                }
+                [b] => format!("{:04X}: {:02X}    DB {:#04X}", address, b, b),
+                _ => unreachable!(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_describes_opcodes() {
+        assert_eq!(mnemonic(0x00E0), "CLS");
+        assert_eq!(mnemonic(0x1234), "JP 0x234");
+        assert_eq!(mnemonic(0x6A02), "LD VA, 0x02");
+        assert_eq!(mnemonic(0x8AB6), "SHR VA, VB");
+        assert_eq!(mnemonic(0xD015), "DRW V0, V1, 5");
+        assert_eq!(mnemonic(0xF265), "LD V2, [I]");
+        assert_eq!(mnemonic(0xE1FF), "DW 0xE1FF");
+    }
+
+    #[test]
+    fn it_disassembles_roms() {
+        let lines = disassemble(&[0x00, 0xE0, 0x12, 0x00, 0xAB], 0x200);
+        assert_eq!(lines, vec![
+            "0200: 00E0  CLS",
+            "0202: 1200  JP 0x200",
+            "0204: AB    DB 0xAB",
+        ]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::ops::{Deref, DerefMut};
+
+/// CHIP-8's usual 64x32 display
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+/// HIRES CHIP-8's display, twice as high
+pub const HIRES_WIDTH: usize = 64;
+pub const HIRES_HEIGHT: usize = 64;
+/// MegaChip's colour display
+pub const MEGACHIP_WIDTH: usize = 256;
+pub const MEGACHIP_HEIGHT: usize = 192;
+
+/// The pixels the interpreter draws into, a row at a time from the top left. Lit pixels are
+/// 0xFFFFFFFF and unlit ones 0, the frontend colours them, unless the display is a coloured one
+/// whose pixels are already RGBA colours. It derefs to the pixels, the size depends on the machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    coloured: bool,
+    pixels: Vec<u32>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new(LORES_WIDTH, LORES_HEIGHT)
+    }
+}
+
+impl Display {
+    /// A blank display
+    pub fn new(width: usize, height: usize) -> Self {
+        Display { width, height, coloured: false, pixels: vec![0; width * height] }
+    }
+
+    /// A blank display whose pixels are RGBA colours, shown as they are
+    pub fn coloured(width: usize, height: usize) -> Self {
+        Display { coloured: true, ..Display::new(width, height) }
+    }
+
+    pub fn is_coloured(&self) -> bool {
+        self.coloured
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut u32 {
+        &mut self.pixels[y * self.width + x]
+    }
+
+    /// The pixels a row at a time
+    pub fn rows(&self) -> impl Iterator<Item = &[u32]> {
+        self.pixels.chunks(self.width)
+    }
+}
+
+impl Deref for Display {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.pixels
+    }
+}
+
+impl DerefMut for Display {
+    fn deref_mut(&mut self) -> &mut [u32] {
+        &mut self.pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_addresses_pixels_by_row() {
+        let mut display = Display::new(64, 64);
+        assert_eq!(display.len(), 64 * 64);
+
+        *display.pixel_mut(3, 2) = 0xFFFFFFFF;
+        assert_eq!(display[2 * 64 + 3], 0xFFFFFFFF);
+        assert_eq!(display.rows().count(), 64);
+        assert_eq!(display.rows().nth(2).unwrap()[3], 0xFFFFFFFF);
+
+        assert_eq!(Display::default().size(), (64, 32));
+        assert!(!Display::default().is_coloured());
+        assert!(Display::coloured(256, 192).is_coloured());
+    }
+}
@@ -0,0 +1,293 @@
+use std::time::{Duration, Instant};
+
+use crate::quirks::Quirks;
+
+pub const GLYPH_HEIGHT: i32 = 5;
+/// Horizontal distance between characters, including the space between them
+pub const CHAR_ADVANCE: i32 = 4;
+/// Vertical distance between lines, including the space between them
+pub const LINE_ADVANCE: i32 = 6;
+
+/// How often the FPS and IPS counters are updated
+const RATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long messages stay on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// Characters that fit on a status line, longer ones are wrapped. The text is scaled to the
+/// display's height, so this is what fits across a square one.
+const STATUS_CHARS: usize = 36;
+
+/// A 3x5 pixel font, each row's pixels are the low three bits with the leftmost in bit 2.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// The lit pixels of a line of text, as (x, y) offsets from its top left corner.
+pub fn text_pixels(text: &str) -> Vec<(i32, i32)> {
+    text.chars()
+        .enumerate()
+        .flat_map(|(i, c)| {
+            glyph(c).iter()
+                .enumerate()
+                .flat_map(move |(y, row)| (0..3)
+                    .filter(move |x| row & (0b100 >> x) != 0)
+                    .map(move |x| (i as i32 * CHAR_ADVANCE + x, y as i32)))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Break text into lines of at most `width` characters between words, a word longer than that
+/// gets a line of its own
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split(' ') {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+/// Width of a line of text in pixels
+pub fn text_width(text: &str) -> i32 {
+    (text.chars().count() as i32 * CHAR_ADVANCE - 1).max(0)
+}
+
+/// Information drawn over the display: how fast the emulator is running, the quirks in use,
+/// and short messages about what just happened.
+pub struct Hud {
+    visible: bool,
+    quirks: String,
+    fps: f32,
+    ips: f32,
+    frames: u32,
+    instructions: u64,
+    interval_start: Option<Instant>,
+    message: Option<(String, Instant)>,
+    prompt: Option<String>,
+    menu: Option<(Vec<String>, usize)>,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            visible: false,
+            quirks: Quirks::default().summary(),
+            fps: 0.0,
+            ips: 0.0,
+            frames: 0,
+            instructions: 0,
+            interval_start: None,
+            message: None,
+            prompt: None,
+            menu: None,
+        }
+    }
+
+    /// Whether the counters are shown, messages are shown regardless
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn set_quirks(&mut self, quirks: &Quirks) {
+        self.quirks = quirks.summary();
+    }
+
+    /// Count instructions executed, for the instructions per second counter
+    pub fn count_instructions(&mut self, instructions: usize) {
+        self.instructions += instructions as u64;
+    }
+
+    /// Count a frame presented, for the frames per second counter
+    pub fn count_frame(&mut self, now: Instant) {
+        // Rates are measured from the first frame onwards
+        let start = match self.interval_start {
+            Some(start) => start,
+            None => {
+                self.instructions = 0;
+                self.interval_start = Some(now);
+                return;
+            }
+        };
+        self.frames += 1;
+
+        let elapsed = now.duration_since(start);
+        if elapsed >= RATE_INTERVAL {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.ips = self.instructions as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.instructions = 0;
+            self.interval_start = Some(now);
+        }
+    }
+
+    pub fn show_message<S: Into<String>>(&mut self, message: S, now: Instant) {
+        self.message = Some((message.into(), now + MESSAGE_DURATION));
+    }
+
+    /// Show text in the middle of the display until it's cleared, e.g. to ask for a ROM
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt;
+    }
+
+    pub fn prompt(&self) -> Option<&str> {
+        self.prompt.as_deref()
+    }
+
+    /// Show a list of items over the display, with one of them highlighted
+    pub fn set_menu(&mut self, menu: Option<(Vec<String>, usize)>) {
+        self.menu = menu;
+    }
+
+    pub fn menu(&self) -> Option<(&[String], usize)> {
+        self.menu.as_ref().map(|(items, selected)| (items.as_slice(), *selected))
+    }
+
+    /// The lines to draw in the top left corner
+    pub fn status(&self) -> Vec<String> {
+        if !self.visible {
+            return Vec::new();
+        }
+
+        let mut lines = vec![
+            format!("{:.0} FPS", self.fps),
+            format!("{:.0} IPS", self.ips),
+        ];
+        lines.extend(wrap(&format!("QUIRKS: {}", self.quirks), STATUS_CHARS));
+        lines
+    }
+
+    /// The message to draw in the bottom left corner, if one was shown recently
+    pub fn message(&self, now: Instant) -> Option<&str> {
+        match &self.message {
+            Some((message, until)) if now < *until => Some(message),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_text() {
+        let pixels = text_pixels("1.");
+        assert_eq!(pixels, vec![
+            (1, 0),
+            (0, 1), (1, 1),
+            (1, 2),
+            (1, 3),
+            (0, 4), (1, 4), (2, 4),
+            (5, 4),
+        ]);
+        assert_eq!(text_width("1."), 7);
+        assert_eq!(text_width(""), 0);
+        assert_eq!(text_pixels("a"), text_pixels("A"));
+        assert_eq!(text_pixels("_"), vec![(0, 4), (1, 4), (2, 4)]);
+    }
+
+    #[test]
+    fn it_counts_frames_and_instructions() {
+        let mut hud = Hud::new();
+        hud.set_visible(true);
+        let start = Instant::now();
+
+        for frame in 0..=30 {
+            hud.count_instructions(10);
+            hud.count_frame(start + Duration::from_millis(frame * 1000 / 60));
+        }
+
+        assert_eq!(hud.status(), vec!["60 FPS", "600 IPS", "QUIRKS: modern"]);
+
+        hud.set_visible(false);
+        assert!(hud.status().is_empty());
+    }
+
+    #[test]
+    fn it_wraps_long_quirks() {
+        let mut hud = Hud::new();
+        hud.set_visible(true);
+        hud.set_quirks(&"vip,+shifting,+jumping,-clipping".parse().unwrap());
+
+        assert_eq!(hud.status()[2..], ["QUIRKS: vip +shifting +jumping", "-clipping"]);
+        assert!(hud.status().iter().all(|line| line.len() <= STATUS_CHARS));
+    }
+
+    #[test]
+    fn it_shows_messages_for_a_while() {
+        let mut hud = Hud::new();
+        let now = Instant::now();
+
+        assert_eq!(hud.message(now), None);
+        hud.show_message("Paused", now);
+        assert_eq!(hud.message(now + Duration::from_secs(1)), Some("Paused"));
+        assert_eq!(hud.message(now + Duration::from_secs(3)), None);
+    }
+}
@@ -1,19 +1,54 @@
 use std::cmp::Ordering;
 use std::fs;
-use std::io::Read;
+use std::io;
+use std::path::Path;
 
-use rand::{Rng, RngCore};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use sha1::{Digest, Sha1};
 
+use crate::audio::SoundEvent;
+use crate::config::Settings;
+use crate::display::Display;
 use crate::font::{FONTSET, FONTSET_SIZE};
+use crate::machine::{ColourOverlay, Machine, MegaChip};
+use crate::quirks::Quirks;
+use crate::timing::{Timing, VIP_INTERPRETER_CYCLES};
+use crate::vip::Vip;
 
 mod font;
+pub mod audio;
+pub mod cdp1802;
+pub mod config;
+pub mod disasm;
+pub mod display;
+pub mod hud;
+pub mod machine;
+pub mod menu;
+pub mod movie;
+pub mod palette;
+pub mod phosphor;
 pub mod platform;
+pub mod quirks;
+pub mod rewind;
+pub mod romdb;
+pub mod timing;
+pub mod vip;
+pub mod watch;
+pub mod wav;
+pub mod window;
 
 const START_ADDRESS: u16 = 0x200;
 const FONTSET_START_ADDRESS: u16 = 0x50;
+const MEMORY_SIZE: usize = 4096;
 
-pub const VIDEO_WIDTH: usize = 64;
-pub const VIDEO_HEIGHT: usize = 32;
+/// Frames the buzzer sounds for after a key is released in FX0A, with the key release quirk
+const KEY_BEEP_FRAMES: u8 = 4;
+
+/// The SHA-1 hash of a ROM as lowercase hex, used to identify it regardless of its file name
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom).iter().map(|b| format!("{:02x}", b)).collect()
+}
 
 pub enum SoundState {
     On,
@@ -22,17 +57,44 @@ pub enum SoundState {
 
 pub struct Chip8 {
     registers: [u8; 16],
-    memory: [u8; 4096],
-    index: u16,
+    memory: Vec<u8>,
+    /// 16 bits on most machines, MegaChip can set all 24 bits of its address space
+    index: u32,
     pc: u16,
     stack: [u16; 16],
     sp: u8,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub keypad: [u8; 16],
-    pub video: [u32; VIDEO_WIDTH * VIDEO_HEIGHT],
+    /// The keypad as it was at the last cycle, to tell when a key is newly pressed
+    previous_keypad: [u8; 16],
+    /// CHIP-8X's second keypad, only EXF2 and EXF5 read it
+    pub second_keypad: [u8; 16],
+    /// The key FX0A saw pressed and is waiting to be released
+    held_key: Option<usize>,
+    /// DXYN is waiting for the next frame to draw, with the display wait quirk
+    waiting_for_vblank: bool,
+    pub video: Display,
     opcode: u16,
-    rand_gen: Box<dyn RngCore>,
+    rand_gen: StdRng,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    /// Which variant of CHIP-8 to run, CHIP-8X and CHIP-8E have instructions of their own
+    pub machine: Machine,
+    /// The CHIP-8X colour board's state
+    colours: ColourOverlay,
+    /// CHIP-8E's FX4F has set the delay timer and is waiting for it to run out
+    waiting_for_timer: bool,
+    /// VIP machine cycles the last instruction ran past the end of the last frame, with VIP timing
+    vip_cycles: u32,
+    /// Set when ROMs run on an emulated COSMAC VIP rather than this interpreter
+    vip: Option<Box<Vip>>,
+    /// Set while a MegaChip program has its colour display and sound switched on
+    mega: Option<Box<MegaChip>>,
+    frame: u64,
+    sound_events: Vec<SoundEvent>,
+    /// Digitised sound played during the last frame
+    samples: Vec<f32>,
 }
 
 impl Default for Chip8 {
@@ -43,19 +105,16 @@ impl Default for Chip8 {
 
 impl Chip8 {
     pub fn new() -> Self {
-        // Init memory pointer
-        let pc = START_ADDRESS;
-
         // Load the font
-        let mut memory = [0; 4096];
+        let mut memory = vec![0; MEMORY_SIZE];
         let start = FONTSET_START_ADDRESS as usize;
         memory[start..start + FONTSET_SIZE].clone_from_slice(&FONTSET);
 
-        // Initialize the random number gen
-        let rand_gen = Box::new(rand::thread_rng());
+        // Initialize the random number gen, it's a seedable one so snapshots can save its state
+        let rand_gen = StdRng::from_entropy();
 
         Chip8 {
-            pc,
+            pc: START_ADDRESS,
             memory,
             registers: [0; 16],
             stack: [0; 16],
@@ -63,21 +122,80 @@ impl Chip8 {
             delay_timer: 0,
             sound_timer: 0,
             keypad: [0; 16],
-            video: [0; VIDEO_WIDTH * VIDEO_HEIGHT],
+            previous_keypad: [0; 16],
+            second_keypad: [0; 16],
+            held_key: None,
+            waiting_for_vblank: false,
+            video: Display::default(),
             index: 0,
             opcode: 0,
             rand_gen,
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            machine: Machine::default(),
+            colours: ColourOverlay::default(),
+            waiting_for_timer: false,
+            vip_cycles: 0,
+            vip: None,
+            mega: None,
+            frame: 0,
+            sound_events: Vec::new(),
+            samples: Vec::new(),
         }
     }
 
-    pub fn load_rom(&mut self, filename: &str) {
-        let mut f = fs::File::open(&filename).expect("file not found");
-        let metadata = fs::metadata(&filename).expect("unable to read file metadata");
-        let mut buffer = vec![0; metadata.len() as usize];
-        f.read_exact(&mut buffer).expect("buffer overflow");
+    /// Use a deterministic random number sequence for CXNN
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rand_gen = StdRng::seed_from_u64(seed);
+    }
 
-        buffer.iter().enumerate()
-            .for_each(|(i, b)| self.memory[START_ADDRESS as usize + i] = *b)
+    /// Run ROMs on an emulated COSMAC VIP with `interpreter`, the original CHIP-8 interpreter, rather
+    /// than this one. Quirks and timing don't apply, the interpreter behaves however it does.
+    pub fn use_vip(&mut self, interpreter: &[u8]) -> io::Result<()> {
+        self.vip = Some(Box::new(Vip::new(interpreter)?));
+        Ok(())
+    }
+
+    /// Use the quirks, timing and machine from `settings`, or the defaults for any that aren't set.
+    /// The VIP machine also needs `use_vip`.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.quirks = settings.quirks.unwrap_or_default();
+        self.timing = settings.timing.unwrap_or_default();
+        self.machine = settings.machine.unwrap_or_default();
+    }
+
+    /// Load a ROM with its settings, the ROM database's recommendations for it overridden by the
+    /// config file and command line
+    pub fn load_rom<P: AsRef<Path>>(&mut self, filename: P, settings: &Settings) -> io::Result<()> {
+        let buffer = fs::read(filename)?;
+        self.apply_settings(settings);
+        self.load_bytes(&buffer)
+    }
+
+    /// Load a ROM where the machine runs programs from, and get ready to run it on a blank display
+    /// of the machine's size
+    pub fn load_bytes(&mut self, rom: &[u8]) -> io::Result<()> {
+        if let Some(vip) = &mut self.vip {
+            return vip.load(rom);
+        }
+
+        let start = self.start_address() as usize;
+        let size = self.memory_size(rom.len());
+        if rom.len() > size - start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ROM is {} bytes but only {} bytes of memory are available", rom.len(), size - start),
+            ));
+        }
+
+        self.memory.resize(size, 0);
+        self.memory[start..start + rom.len()].clone_from_slice(rom);
+        self.pc = start as u16;
+        let (width, height) = self.display_size();
+        if self.video.size() != (width, height) {
+            self.video = Display::new(width, height);
+        }
+        Ok(())
     }
 
     fn rand_byte(&mut self) -> u8 {
@@ -87,13 +205,132 @@ impl Chip8 {
 
     /// Returns true is sound is playing
     pub fn sound_state(&self) -> SoundState {
-        if self.sound_timer > 0 {
+        if self.sound() {
             SoundState::On
         } else {
             SoundState::Off
         }
     }
 
+    /// Run a single 60 Hz frame: execute a batch of instructions, then count down the timers once.
+    /// With VIP timing the number of instructions depends on how long they take rather than
+    /// `instructions`, and with the display wait quirk the frame ends early when DXYN starts
+    /// waiting for the next one. Returns the number of instructions executed.
+    pub fn run_frame(&mut self, instructions: usize) -> usize {
+        // Keep anything recorded since the last frame ended, i.e. by restoring a snapshot
+        let frame = self.frame;
+        self.sound_events.retain(|event| event.frame == frame);
+        self.samples.clear();
+
+        if let Some(vip) = &mut self.vip {
+            let executed = vip.run_frame(&self.keypad, &mut self.video);
+            let events = vip.sound_changes().iter().map(|(offset, on)| SoundEvent { frame, offset: *offset, on: *on });
+            self.sound_events.extend(events);
+
+            self.frame += 1;
+            return executed;
+        }
+
+        let executed = match self.timing {
+            Timing::Fixed => self.run_instructions(instructions),
+            Timing::Vip => self.run_vip_cycles(),
+        };
+
+        let sound = self.sound_timer > 0;
+        self.tick_timers();
+        self.record_sound(sound, 1.0);
+        self.play_digitised_sound();
+
+        self.frame += 1;
+        executed
+    }
+
+    /// Switch to another machine, e.g. one freshly booted on reset. It carries on from this one's
+    /// frame so its sound keeps to the same schedule, and the buzzer stopping, if this one left it
+    /// on, is reported with the next frame's sound events.
+    pub fn replace(&mut self, other: Chip8) {
+        let sound = self.sound();
+        let frame = self.frame;
+
+        *self = other;
+        self.frame = frame;
+        self.sound_events.clear();
+        self.record_sound(sound, 0.0);
+    }
+
+    /// Whether how many instructions run each frame is set by VIP timing or the emulated VIP, so
+    /// `run_frame` ignores the number it's given
+    pub fn fixed_speed(&self) -> bool {
+        self.vip.is_some() || self.timing == Timing::Vip
+    }
+
+    fn run_instructions(&mut self, instructions: usize) -> usize {
+        for i in 0..instructions {
+            let sound = self.sound_timer > 0;
+            self.cycle();
+            self.record_sound(sound, i as f32 / instructions as f32);
+
+            if self.waiting_for_vblank {
+                return i + 1;
+            }
+        }
+
+        instructions
+    }
+
+    /// Run instructions until the machine cycles the VIP had for them each frame are used up,
+    /// anything the last one runs over by comes out of the next frame.
+    fn run_vip_cycles(&mut self) -> usize {
+        let mut executed = 0;
+
+        while self.vip_cycles < VIP_INTERPRETER_CYCLES {
+            let sound = self.sound_timer > 0;
+            let offset = self.vip_cycles as f32 / VIP_INTERPRETER_CYCLES as f32;
+            let registers = self.registers;
+            let pc = self.pc;
+
+            self.cycle();
+            let skipped = matches!(self.opcode >> 12, 0x3 | 0x4 | 0x5 | 0x9 | 0xE) && self.pc == pc + 4;
+            self.vip_cycles += timing::vip_cycles(self.opcode, &registers, skipped);
+            self.record_sound(sound, offset);
+            executed += 1;
+
+            if self.waiting_for_vblank {
+                self.vip_cycles = VIP_INTERPRETER_CYCLES;
+                break;
+            }
+        }
+
+        self.vip_cycles -= VIP_INTERPRETER_CYCLES;
+        executed
+    }
+
+    /// The times the sound started or stopped during the last frame
+    pub fn sound_events(&self) -> &[SoundEvent] {
+        &self.sound_events
+    }
+
+    /// The digitised sound played during the last frame, up to a frame's worth of samples at
+    /// `SAMPLE_RATE`, or none if nothing was playing
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Whether the buzzer is sounding
+    fn sound(&self) -> bool {
+        match &self.vip {
+            Some(vip) => vip.sound(),
+            None => self.sound_timer > 0,
+        }
+    }
+
+    fn record_sound(&mut self, before: bool, offset: f32) {
+        let on = self.sound();
+        if on != before {
+            self.sound_events.push(SoundEvent { frame: self.frame, offset, on });
+        }
+    }
+
     pub fn cycle(&mut self) {
         // Fetch
         self.opcode = ((self.memory[self.pc as usize] as u16) << 8) | (self.memory[self.pc as usize + 1] as u16);
@@ -104,6 +341,11 @@ impl Chip8 {
         // Decode and execute
         self.call_op();
 
+        self.previous_keypad = self.keypad;
+    }
+
+    /// Count down the delay and sound timers, this should be called at 60 Hz
+    pub fn tick_timers(&mut self) {
         // Decrement the delay timer if it's been set
         if self.delay_timer > 0 { self.delay_timer -= 1; }
 
@@ -131,6 +373,20 @@ impl Chip8 {
         self.pc -= 2;
     }
 
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn shift_source(&self) -> u8 {
+        if self.quirks.shifting {
+            self.registers[self.vx()]
+        } else {
+            self.registers[self.vy()]
+        }
+    }
+
     fn op_00e0(&mut self) {
         // Clear the display.
         self.video.fill(0);
@@ -198,16 +454,19 @@ impl Chip8 {
     fn op_8xy1(&mut self) {
         // Set Vx = Vx OR Vy.
         self.registers[self.vx()] |= self.registers[self.vy()];
+        self.reset_vf();
     }
 
     fn op_8xy2(&mut self) {
         // Set Vx = Vx AND Vy.
         self.registers[self.vx()] &= self.registers[self.vy()];
+        self.reset_vf();
     }
 
     fn op_8xy3(&mut self) {
         // Set Vx = Vx XOR Vy.
         self.registers[self.vx()] ^= self.registers[self.vy()];
+        self.reset_vf();
     }
 
     fn op_8xy4(&mut self) {
@@ -240,8 +499,9 @@ impl Chip8 {
         // Set Vx = Vx SHR 1.
         // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0.
         // Then Vx is divided by 2.
-        self.registers[0xF] = self.registers[self.vx()] & 0x1;
-        self.registers[self.vx()] >>= 1;
+        let value = self.shift_source();
+        self.registers[self.vx()] = value >> 1;
+        self.registers[0xF] = value & 0x1;
     }
 
     fn op_8xy7(&mut self) {
@@ -260,8 +520,9 @@ impl Chip8 {
         // Set Vx = Vx SHL 1.
         // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
         // Then Vx is multiplied by 2.
-        self.registers[0xF] = (self.registers[self.vx()] & 0x80u8) >> 7;
-        self.registers[self.vx()] <<= 1;
+        let value = self.shift_source();
+        self.registers[self.vx()] = value << 1;
+        self.registers[0xF] = (value & 0x80u8) >> 7;
     }
 
     fn op_9xy0(&mut self) {
@@ -273,13 +534,14 @@ impl Chip8 {
 
     fn op_annn(&mut self) {
         // Set I = nnn.
-        self.index = self.opcode & 0x0FFF;
+        self.index = (self.opcode & 0x0FFF) as u32;
     }
 
     fn op_bnnn(&mut self) {
         // Jump to location nnn + V0.
         let address = self.opcode & 0x0FFF;
-        self.pc = self.registers[0] as u16 + address;
+        let offset = if self.quirks.jumping { self.registers[self.vx()] } else { self.registers[0] };
+        self.pc = offset as u16 + address;
     }
 
     fn op_cxkk(&mut self) {
@@ -289,25 +551,39 @@ impl Chip8 {
 
     fn op_dxyn(&mut self) {
         // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+        if self.quirks.display_wait {
+            // Stall until the next frame, then draw when this instruction runs again
+            self.waiting_for_vblank = !self.waiting_for_vblank;
+            if self.waiting_for_vblank {
+                return self.repeat_last_inst();
+            }
+        }
+
         let vx = self.vx();
         let vy = self.vy();
         let height = self.opcode & 0x000F;
 
-        let x_pos = self.registers[vx];
-        let y_pos = self.registers[vy];
+        // The starting position always wraps onto the screen
+        let (screen_width, screen_height) = (self.video.width() as u16, self.video.height() as u16);
+        let x_pos = self.registers[vx] as u16 % screen_width;
+        let y_pos = self.registers[vy] as u16 % screen_height;
+        let clipping = self.quirks.clipping;
 
         self.registers[0xF] = 0;
 
         (0..height).collect::<Vec<u16>>().iter().for_each(|row| {
-            let sprite_byte = self.memory[(self.index + row) as usize];
+            let sprite_byte = self.memory[self.index as usize + *row as usize];
 
             (0..8).collect::<Vec<u16>>().iter().for_each(|col| {
-                // Wrap if going beyond screen boundaries
-                let x_pos = (x_pos as u16 + col) % VIDEO_WIDTH as u16;
-                let y_pos = (y_pos as u16 + row) % VIDEO_HEIGHT as u16;
+                // Clip or wrap if going beyond screen boundaries
+                if clipping && (x_pos + col >= screen_width || y_pos + row >= screen_height) {
+                    return;
+                }
+                let x_pos = (x_pos + col) % screen_width;
+                let y_pos = (y_pos + row) % screen_height;
 
                 let sprite_pixel: u16 = sprite_byte as u16 & (0x80 >> col);
-                let screen_pixel: &mut u32 = &mut self.video[(y_pos * VIDEO_WIDTH as u16 + x_pos) as usize];
+                let screen_pixel: &mut u32 = self.video.pixel_mut(x_pos as usize, y_pos as usize);
 
                 // Sprite pixel is on
                 if sprite_pixel != 0 {
@@ -347,12 +623,37 @@ impl Chip8 {
 
     fn op_fx0a(&mut self) {
         // Wait for a key press, store the value of the key in Vx.
+        if self.quirks.key_release {
+            return self.wait_for_key_release();
+        }
+
         match self.keypad.iter().position(|k| *k > 0) {
             Some(i) => self.registers[self.vx()] = i as u8,
             None => self.repeat_last_inst()
         };
     }
 
+    /// FX0A as the COSMAC VIP does it: wait for a key to be pressed and released, beeping while
+    /// it's held. A key that was already down when the wait started doesn't count.
+    fn wait_for_key_release(&mut self) {
+        let key = match self.held_key {
+            Some(key) => key,
+            None => match (0..16).find(|k| self.keypad[*k] != 0 && self.previous_keypad[*k] == 0) {
+                Some(key) => key,
+                None => return self.repeat_last_inst(),
+            },
+        };
+
+        if self.keypad[key] != 0 {
+            self.held_key = Some(key);
+            self.sound_timer = self.sound_timer.max(KEY_BEEP_FRAMES);
+            self.repeat_last_inst();
+        } else {
+            self.held_key = None;
+            self.registers[self.vx()] = key as u8;
+        }
+    }
+
     fn op_fx15(&mut self) {
         // Set delay timer = Vx.
         self.delay_timer = self.registers[self.vx()];
@@ -365,12 +666,12 @@ impl Chip8 {
 
     fn op_fx1e(&mut self) {
         // Set I = I + Vx.
-        self.index += self.registers[self.vx() as usize] as u16;
+        self.index += self.registers[self.vx() as usize] as u32;
     }
 
     fn op_fx29(&mut self) {
         // Set I = location of sprite for digit Vx.
-        self.index = FONTSET_START_ADDRESS + (5 * self.registers[self.vx() as usize]) as u16;
+        self.index = FONTSET_START_ADDRESS as u32 + (5 * self.registers[self.vx() as usize]) as u32;
     }
 
     fn op_fx33(&mut self) {
@@ -396,17 +697,29 @@ impl Chip8 {
         // Store registers V0 through Vx in memory starting at location I.
         let vx = self.vx() as usize;
         let s = self.index as usize;
-        self.memory[s..=s + vx].clone_from_slice(&self.registers[0..=vx])
+        self.memory[s..=s + vx].clone_from_slice(&self.registers[0..=vx]);
+
+        if self.quirks.memory {
+            self.index += vx as u32 + 1;
+        }
     }
 
     fn op_fx65(&mut self) {
         // Read registers V0 through Vx from memory starting at location I.
         let vx = self.vx() as usize;
         let s = self.index as usize;
-        self.registers[0..=vx].clone_from_slice(&self.memory[s..=s + vx])
+        self.registers[0..=vx].clone_from_slice(&self.memory[s..=s + vx]);
+
+        if self.quirks.memory {
+            self.index += vx as u32 + 1;
+        }
     }
 
     pub fn call_op(&mut self) {
+        if self.call_variant_op() {
+            return;
+        }
+
         let n1 = self.opcode >> (4 * 3);
         let n34 = self.opcode & 0x00FF;
         let n4 = self.opcode & 0x000F;
@@ -468,8 +781,16 @@ impl Chip8 {
 mod tests {
     use std::convert::TryInto;
 
+    use crate::audio::SoundScheduler;
+    use crate::romdb::RomDatabase;
+
     use super::*;
 
+    #[test]
+    fn it_hashes_roms() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
     #[test]
     fn it_returns_vx() {
         let mut chp8 = Chip8::new();
@@ -491,6 +812,106 @@ mod tests {
         assert_eq!(chp8.last_byte(), 0x34);
     }
 
+    #[test]
+    fn it_loads_rom_bytes() {
+        let mut chp8 = Chip8::new();
+        chp8.load_bytes(&[0x12, 0x34]).unwrap();
+        assert_eq!(chp8.memory[0x200], 0x12);
+        assert_eq!(chp8.memory[0x201], 0x34);
+
+        // Too large to fit in memory after the start address
+        assert!(chp8.load_bytes(&[0; 4096 - 0x1FF]).is_err());
+    }
+
+    #[test]
+    fn it_loads_roms_with_their_settings() {
+        let rom = [0x12, 0x00];
+        let path = std::env::temp_dir().join(format!("chip8-rs-test-{}.ch8", std::process::id()));
+        fs::write(&path, rom).unwrap();
+
+        let programs = r#"[{ "title": "Loop", "roms": { "HASH": { "platforms": ["originalChip8"] } } }]"#
+            .replace("HASH", &rom_hash(&rom));
+        let hashes = format!(r#"{{ "{}": 0 }}"#, rom_hash(&rom));
+        let database = RomDatabase::parse(
+            include_str!("../data/chip-8-database/platforms.json"), &programs, &hashes).unwrap();
+
+        let info = database.lookup(&rom_hash(&rom));
+        assert_eq!(info.title.as_deref(), Some("Loop"));
+
+        let mut chp8 = Chip8::new();
+        chp8.load_rom(&path, &info.settings()).unwrap();
+        assert_eq!(chp8.quirks, Quirks::VIP);
+        assert_eq!(chp8.memory[0x200..0x202], rom);
+
+        // The config file and command line take precedence over the database
+        let config = Settings { quirks: Some(Quirks::SCHIP), timing: Some(Timing::Vip), ..Settings::default() };
+        let mut chp8 = Chip8::new();
+        chp8.load_rom(&path, &info.settings().merge(&config)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(chp8.quirks, Quirks::SCHIP);
+        assert_eq!(chp8.timing, Timing::Vip);
+    }
+
+    #[test]
+    fn it_runs_a_frame() {
+        let mut chp8 = Chip8::new();
+        chp8.delay_timer = 10;
+        chp8.sound_timer = 1;
+
+        // 6001, 7001 in a loop with 1200
+        chp8.memory[0x200..0x206].clone_from_slice(&[0x60, 0x00, 0x70, 0x01, 0x12, 0x02]);
+        chp8.run_frame(9);
+
+        // The first instruction is the only one that isn't repeated
+        assert_eq!(chp8.registers[0], 4);
+
+        // Timers only tick once per frame, regardless of the instruction count
+        assert_eq!(chp8.delay_timer, 9);
+        assert_eq!(chp8.sound_timer, 0);
+
+        chp8.run_frame(0);
+        assert_eq!(chp8.delay_timer, 8);
+        assert_eq!(chp8.sound_timer, 0);
+    }
+
+    #[test]
+    fn it_records_sound_events() {
+        let mut chp8 = Chip8::new();
+
+        // 6002, F018 then loop with 1204
+        chp8.memory[0x200..0x206].clone_from_slice(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]);
+        chp8.run_frame(4);
+        assert_eq!(chp8.sound_events(), [SoundEvent { frame: 0, offset: 0.25, on: true }]);
+
+        chp8.run_frame(4);
+        assert_eq!(chp8.sound_events(), [SoundEvent { frame: 1, offset: 1.0, on: false }]);
+
+        chp8.run_frame(4);
+        assert!(chp8.sound_events().is_empty());
+    }
+
+    #[test]
+    fn it_stops_the_buzzer_when_replaced() {
+        // 100 samples per frame, no latency
+        let mut scheduler = SoundScheduler::new(6000, 60, 0);
+        let mut chp8 = Chip8::new();
+
+        // 600A, F018 then loop with 1204, the buzzer comes on half way through frame 0
+        chp8.memory[0x200..0x206].clone_from_slice(&[0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04]);
+        chp8.run_frame(2);
+        chp8.sound_events().iter().for_each(|event| scheduler.push(*event));
+        chp8.run_frame(2);
+        assert!((0..100).all(|_| scheduler.next_gate()));
+
+        // The new machine never sounds, so only the old one can say the buzzer's off
+        chp8.replace(Chip8::new());
+        chp8.run_frame(1);
+        assert_eq!(chp8.sound_events(), [SoundEvent { frame: 2, offset: 0.0, on: false }]);
+        chp8.sound_events().iter().for_each(|event| scheduler.push(*event));
+        assert!((0..50).all(|_| scheduler.next_gate()));
+        assert!((0..100).all(|_| !scheduler.next_gate()));
+    }
+
     #[test]
     fn test_op_00e0() {
         let mut chp8 = Chip8::new();
@@ -691,6 +1112,19 @@ mod tests {
         assert_eq!(chp8.registers[0x04], 0x04 ^ 0x0F);
     }
 
+    #[test]
+    fn test_op_8xy1_vf_reset_quirk() {
+        // VF is reset after logical operations on the VIP
+        let mut chp8 = Chip8::new();
+        chp8.quirks = Quirks::VIP;
+        chp8.registers = (0x00..0x10).collect::<Vec<u8>>().try_into().unwrap();
+
+        chp8.opcode = 0x8031;
+        chp8.op_8xy1();
+        assert_eq!(chp8.registers[0x00], 0x03);
+        assert_eq!(chp8.registers[0x0F], 0);
+    }
+
     #[test]
     fn test_op_8xy4() {
         // Set Vx = Vx + Vy, set VF = carry.
@@ -764,6 +1198,26 @@ mod tests {
         chp8.registers[1..0xF].iter().for_each(|b| assert_eq!(*b, 0b00001001));
     }
 
+    #[test]
+    fn test_op_8xy6_shifting_quirk() {
+        // Without the shifting quirk Vy is shifted into Vx
+        let mut chp8 = Chip8::new();
+        chp8.quirks = Quirks::VIP;
+        chp8.registers[0x0] = 0b00001000;
+        chp8.registers[0x1] = 0b00000011;
+        chp8.opcode = 0x8016;
+
+        chp8.op_8xy6();
+        assert_eq!(chp8.registers[0x0], 0b00000001);
+        assert_eq!(chp8.registers[0x1], 0b00000011);
+        assert_eq!(chp8.registers[0xF], 0x1);
+
+        chp8.opcode = 0x801E;
+        chp8.op_8xye();
+        assert_eq!(chp8.registers[0x0], 0b00000110);
+        assert_eq!(chp8.registers[0xF], 0x0);
+    }
+
     #[test]
     fn test_op_8xy7() {
         // Set Vx = Vy - Vx, set VF = NOT borrow.
@@ -855,6 +1309,35 @@ mod tests {
         assert_eq!(chp8.pc, 0x0010 + 0x0123);
     }
 
+    #[test]
+    fn test_op_bnnn_jumping_quirk() {
+        // Jump to location xnn + Vx.
+        let mut chp8 = Chip8::new();
+        chp8.quirks = Quirks::SCHIP;
+        chp8.registers[0x0] = 0x0010;
+        chp8.registers[0x1] = 0x0020;
+        chp8.opcode = 0xb123;
+
+        chp8.op_bnnn();
+        assert_eq!(chp8.pc, 0x0020 + 0x0123);
+    }
+
+    #[test]
+    fn it_generates_seeded_random_numbers() {
+        let mut a = Chip8::new();
+        let mut b = Chip8::new();
+        a.set_seed(42);
+        b.set_seed(42);
+
+        a.opcode = 0xc0ff;
+        b.opcode = 0xc0ff;
+        (0..16).for_each(|_| {
+            a.op_cxkk();
+            b.op_cxkk();
+            assert_eq!(a.registers[0x0], b.registers[0x0]);
+        });
+    }
+
     #[test]
     #[ignore] // TODO: How to test random number gen?
     fn test_op_cxkk() {
@@ -905,7 +1388,81 @@ mod tests {
         chp8.opcode = 0xd122;
 
         chp8.op_dxyn();
-        assert_eq!(chp8.video[2 * VIDEO_WIDTH + 1], 0xFFFFFFFF);
+        assert_eq!(chp8.video[2 * chp8.video.width() + 1], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn it_runs_instructions_for_as_long_as_they_took_on_the_vip() {
+        let mut chp8 = Chip8::new();
+        chp8.timing = Timing::Vip;
+        chp8.load_bytes(&[0x61, 0x05, 0x12, 0x00]).unwrap();
+
+        // 46 and 52 machine cycles, alternating, the last one running past the end of the frame
+        assert_eq!(chp8.run_frame(10), 54);
+        assert_eq!(chp8.vip_cycles, 48);
+        assert_eq!(chp8.run_frame(10), 53);
+        assert!(chp8.fixed_speed());
+
+        chp8.timing = Timing::Fixed;
+        assert_eq!(chp8.run_frame(10), 10);
+        assert!(!chp8.fixed_speed());
+    }
+
+    #[test]
+    fn test_op_dxyn_clipping() {
+        // Sprites starting off screen wrap, sprites going off the edge are clipped
+        let mut chp8 = Chip8::new();
+        chp8.quirks.clipping = true;
+        chp8.index = 0;
+        chp8.memory[0] = 0xFF;
+        chp8.memory[1] = 0xFF;
+        chp8.registers[0] = chp8.video.width() as u8 + 60;
+        chp8.registers[1] = chp8.video.height() as u8 - 1;
+        chp8.opcode = 0xd012;
+
+        chp8.op_dxyn();
+        let lit: Vec<usize> = (0..chp8.video.len()).filter(|i| chp8.video[*i] != 0).collect();
+        let last_row = (chp8.video.height() - 1) * chp8.video.width();
+        assert_eq!(lit, vec![last_row + 60, last_row + 61, last_row + 62, last_row + 63]);
+
+        // Or every pixel wraps around without the quirk, as by default
+        let mut chp8 = Chip8::new();
+        chp8.index = 0;
+        chp8.memory[0] = 0xFF;
+        chp8.memory[1] = 0xFF;
+        chp8.registers[0] = 60;
+        chp8.registers[1] = chp8.video.height() as u8 - 1;
+        chp8.opcode = 0xd012;
+
+        chp8.op_dxyn();
+        assert_eq!(chp8.video.iter().filter(|p| **p != 0).count(), 16);
+        assert_eq!(chp8.video[0], 0xFFFFFFFF);
+        assert_eq!(chp8.video[last_row + 3], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn test_op_dxyn_display_wait() {
+        // Draw at most one sprite per frame, each waiting for the start of the next frame
+        let mut chp8 = Chip8::new();
+        chp8.quirks.display_wait = true;
+        chp8.index = FONTSET_START_ADDRESS as u32;
+        chp8.load_bytes(&[0xD0, 0x05, 0x70, 0x08, 0x12, 0x00]).unwrap();
+
+        chp8.run_frame(10);
+        assert_eq!(chp8.pc, START_ADDRESS);
+        assert!(chp8.video.iter().all(|p| *p == 0));
+
+        chp8.run_frame(10);
+        assert_eq!(chp8.pc, START_ADDRESS);
+        assert_eq!(chp8.video[0], 0xFFFFFFFF);
+        assert_eq!(chp8.registers[0x0], 8);
+
+        // Without the quirk all ten instructions run
+        let mut chp8 = Chip8::new();
+        chp8.index = FONTSET_START_ADDRESS as u32;
+        chp8.load_bytes(&[0xD0, 0x05, 0x70, 0x08, 0x12, 0x00]).unwrap();
+        chp8.run_frame(10);
+        assert_eq!(chp8.registers[0x0], 24);
     }
 
     #[test]
@@ -992,6 +1549,33 @@ mod tests {
         assert_eq!(chp8.registers[0x0], 0xA);
     }
 
+    #[test]
+    fn test_op_fx0a_key_release() {
+        // Wait for a key to be pressed and released, store the value of the key in Vx.
+        let mut chp8 = Chip8::new();
+        chp8.quirks.key_release = true;
+        chp8.load_bytes(&[0xF0, 0x0A]).unwrap();
+
+        // A key held from before the wait doesn't count
+        chp8.keypad[0x1] = 1;
+        chp8.previous_keypad[0x1] = 1;
+        chp8.cycle();
+        chp8.cycle();
+        assert_eq!(chp8.pc, START_ADDRESS);
+
+        // Pressing another key beeps until it's released
+        chp8.keypad[0xA] = 1;
+        chp8.cycle();
+        chp8.cycle();
+        assert_eq!(chp8.pc, START_ADDRESS);
+        assert_eq!(chp8.sound_timer, KEY_BEEP_FRAMES);
+
+        chp8.keypad[0xA] = 0;
+        chp8.cycle();
+        assert_eq!(chp8.pc, START_ADDRESS + 2);
+        assert_eq!(chp8.registers[0x0], 0xA);
+    }
+
     #[test]
     fn test_op_fx15() {
         // Set delay timer = Vx.
@@ -1087,6 +1671,22 @@ mod tests {
         assert_eq!(chp8.memory[0x2 + 4], 0);
     }
 
+    #[test]
+    fn test_op_fx55_memory_quirk() {
+        // I is left pointing past the last register stored on the VIP
+        let mut chp8 = Chip8::new();
+        chp8.quirks = Quirks::VIP;
+        chp8.opcode = 0xF355;
+        chp8.index = 0x2;
+
+        chp8.op_fx55();
+        assert_eq!(chp8.index, 0x2 + 4);
+
+        chp8.opcode = 0xF165;
+        chp8.op_fx65();
+        assert_eq!(chp8.index, 0x2 + 4 + 2);
+    }
+
     #[test]
     fn test_op_fx65() {
         // Read registers V0 through Vx from memory starting at location I.
@@ -0,0 +1,831 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::audio::SAMPLE_RATE;
+use crate::palette::Palette;
+use crate::timing::FRAME_RATE;
+use crate::{Chip8, MEMORY_SIZE, START_ADDRESS};
+use crate::display::{Display, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, MEGACHIP_HEIGHT, MEGACHIP_WIDTH};
+
+/// What runs ROMs: this emulator's interpreter for CHIP-8 or one of its early variants, or an
+/// emulated COSMAC VIP running the original interpreter, which is what hybrid ROMs with 1802
+/// machine code in them need.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Machine {
+    Chip8,
+    /// CHIP-8X, for the VIP with the VP-590 colour board: programs start at 0300 and can colour
+    /// the display
+    Chip8X,
+    /// CHIP-8E, CHIP-8 with extra instructions for relative jumps, waiting on the delay timer and
+    /// saving and loading ranges of registers
+    Chip8E,
+    /// HIRES CHIP-8, with a 64x64 display made of two pages of the VIP's memory. Its ROMs start by
+    /// jumping to 0260 into the interpreter patch they carry, which is done here by jumping
+    /// straight to the program at 02C0.
+    Hires,
+    /// MegaChip, CHIP-8 with a mode that switches to a 256x192 display in 256 colours, with
+    /// large sprites that can be blended into it, and digitised sound
+    MegaChip,
+    Vip,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Machine::Chip8
+    }
+}
+
+const NAMES: [(&str, Machine); 6] = [
+    ("chip8", Machine::Chip8),
+    ("chip8x", Machine::Chip8X),
+    ("chip8e", Machine::Chip8E),
+    ("hires", Machine::Hires),
+    ("megachip", Machine::MegaChip),
+    ("vip", Machine::Vip),
+];
+
+impl FromStr for Machine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NAMES.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, machine)| *machine)
+            .ok_or_else(|| format!("unknown machine '{}', expected chip8, chip8x, chip8e, hires, megachip or vip", s))
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = NAMES.iter().find(|(_, machine)| machine == self).map(|(name, _)| *name).unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+/// The machine a ROM needs, if it can be told from the ROM itself: MegaChip ROMs start by
+/// switching MegaChip mode on. HIRES ROMs start by jumping to 0260, but so might any CHIP-8 ROM
+/// with data to skip over, so they aren't recognised.
+pub fn detect(rom: &[u8]) -> Option<Machine> {
+    if rom.starts_with(&MEGACHIP_ON.to_be_bytes()) {
+        return Some(Machine::MegaChip);
+    }
+    None
+}
+
+/// The first instruction of a HIRES ROM, and where its program really starts
+const HIRES_ENTRY: u16 = 0x1260;
+const HIRES_START_ADDRESS: u16 = 0x2C0;
+/// HIRES clears its larger display with 0230 rather than 00E0
+const HIRES_CLEAR: u16 = 0x0230;
+
+/// CHIP-8X's interpreter is bigger, so programs start later
+const CHIP8X_START_ADDRESS: u16 = 0x300;
+
+/// 0011 switches MegaChip mode on and 0010 back off
+const MEGACHIP_ON: u16 = 0x0011;
+const MEGACHIP_OFF: u16 = 0x0010;
+/// MegaChip ROMs carry their graphics and sound, so memory is made as big as the ROM needs, at
+/// least what 16 bit addresses reach and at most what 24 bit ones do
+const MEGACHIP_MIN_MEMORY_SIZE: usize = 0x10000;
+const MEGACHIP_MAX_MEMORY_SIZE: usize = 0x1000000;
+/// The header digitised sound starts with: a 2 byte sample rate, a 3 byte length and a byte unused
+const SOUND_HEADER_SIZE: usize = 6;
+
+/// The VP-590's colours, by the 3 bit code BXYN takes
+const COLOURS: [u32; 8] = [
+    0x000000FF, // black
+    0xFF0000FF, // red
+    0x0000FFFF, // blue
+    0xFF00FFFF, // violet
+    0x00FF00FF, // green
+    0xFFFF00FF, // yellow
+    0x00FFFFFF, // aqua
+    0xFFFFFFFF, // white
+];
+const RED: u8 = 1;
+
+/// The background colours 02A0 steps through, in order
+const BACKGROUNDS: [u32; 4] = [
+    0x000080FF, // blue
+    0x000000FF, // black
+    0x008000FF, // green
+    0x800000FF, // red
+];
+
+/// Lit pixels are coloured in zones 8 pixels wide and 1 high, BXY0 colours blocks of 4 zones high.
+/// The colour board covers the usual 64x32 display.
+const ZONE_WIDTH: usize = 8;
+const ZONE_COLUMNS: usize = LORES_WIDTH / ZONE_WIDTH;
+const BLOCK_HEIGHT: usize = 4;
+
+/// CHIP-8X colour: a foreground colour for each zone of the display and one background colour
+/// for all of it. It's separate from the display, so drawing doesn't change it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColourOverlay {
+    background: usize,
+    zones: [u8; ZONE_COLUMNS * LORES_HEIGHT],
+}
+
+impl Default for ColourOverlay {
+    /// Red on blue, as the colour board starts up
+    fn default() -> Self {
+        ColourOverlay { background: 0, zones: [RED; ZONE_COLUMNS * LORES_HEIGHT] }
+    }
+}
+
+impl ColourOverlay {
+    /// The colours to draw a pixel with
+    pub fn palette(&self, x: usize, y: usize) -> Palette {
+        let zone = self.zones[y * ZONE_COLUMNS + x / ZONE_WIDTH];
+        Palette { foreground: COLOURS[zone as usize], background: BACKGROUNDS[self.background] }
+    }
+
+    fn next_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+    }
+
+    /// Colour zones, anything past the edges of the display is left out
+    fn fill(&mut self, columns: Range<usize>, rows: Range<usize>, colour: u8) {
+        for row in rows.start..rows.end.min(LORES_HEIGHT) {
+            for column in columns.start..columns.end.min(ZONE_COLUMNS) {
+                self.zones[row * ZONE_COLUMNS + column] = colour & 0x7;
+            }
+        }
+    }
+}
+
+/// How MegaChip draws sprite pixels onto what's already on the display, chosen with 080N
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Blend {
+    Normal,
+    /// The sprite is 25% opaque
+    Quarter,
+    /// The sprite is 50% opaque
+    Half,
+    Add,
+    Multiply,
+}
+
+impl Blend {
+    fn from_code(code: u8) -> Option<Blend> {
+        [Blend::Normal, Blend::Quarter, Blend::Half, Blend::Add, Blend::Multiply].get(code as usize).copied()
+    }
+
+    /// Blend RGBA colours a channel at a time, the result is opaque
+    fn apply(self, source: u32, destination: u32) -> u32 {
+        let channel = |shift: u32| {
+            let (s, d) = ((source >> shift) & 0xFF, (destination >> shift) & 0xFF);
+            let blended = match self {
+                Blend::Normal => s,
+                Blend::Quarter => (s + 3 * d) / 4,
+                Blend::Half => (s + d) / 2,
+                Blend::Add => (s + d).min(0xFF),
+                Blend::Multiply => s * d / 0xFF,
+            };
+            blended << shift
+        };
+        channel(24) | channel(16) | channel(8) | 0xFF
+    }
+}
+
+/// Digitised sound playing from memory, 8 bit unsigned samples
+#[derive(Clone, Copy, Debug)]
+struct DigitisedSound {
+    start: usize,
+    length: usize,
+    rate: u32,
+    looping: bool,
+    /// The sample playing, with how far through it playback is
+    position: f64,
+}
+
+/// MegaChip mode's state. Sprites are drawn into a buffer that 00E0 shows on the display and
+/// clears, with the palette index of each pixel kept to check for collisions.
+#[derive(Clone, Debug)]
+pub(crate) struct MegaChip {
+    /// RGBA colours, 0 is never drawn so sprites can have transparent pixels
+    palette: [u32; 256],
+    sprite_width: usize,
+    sprite_height: usize,
+    /// How opaque the display is shown, set by 05NN
+    alpha: u8,
+    blend: Blend,
+    /// Drawing over a pixel of this palette index sets VF, set by 09NN
+    collision_colour: Option<u8>,
+    buffer: Display,
+    indices: Vec<u8>,
+    sound: Option<DigitisedSound>,
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        MegaChip {
+            palette: [0; 256],
+            sprite_width: 8,
+            sprite_height: 8,
+            alpha: 0xFF,
+            blend: Blend::Normal,
+            collision_colour: None,
+            buffer: Display::coloured(MEGACHIP_WIDTH, MEGACHIP_HEIGHT),
+            indices: vec![0; MEGACHIP_WIDTH * MEGACHIP_HEIGHT],
+            sound: None,
+        }
+    }
+}
+
+impl MegaChip {
+    /// Draw a sprite with its top left corner at (x, y), clipped to the display. Returns whether
+    /// it drew over the collision colour.
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        for (i, colour) in sprite.iter().enumerate() {
+            let (x, y) = (x + i % self.sprite_width, y + i / self.sprite_width);
+            if *colour == 0 || x >= MEGACHIP_WIDTH || y >= MEGACHIP_HEIGHT {
+                continue;
+            }
+
+            let pixel = y * MEGACHIP_WIDTH + x;
+            collision |= self.collision_colour == Some(self.indices[pixel]);
+            self.indices[pixel] = *colour;
+            self.buffer[pixel] = self.blend.apply(self.palette[*colour as usize], self.buffer[pixel]);
+        }
+        collision
+    }
+
+    /// Move everything drawn up by `lines`, leaving blank lines at the bottom
+    fn scroll_up(&mut self, lines: usize) {
+        let shift = lines.min(MEGACHIP_HEIGHT) * MEGACHIP_WIDTH;
+        self.buffer.copy_within(shift.., 0);
+        self.indices.copy_within(shift.., 0);
+        let end = self.indices.len() - shift;
+        self.buffer[end..].fill(0);
+        self.indices[end..].fill(0);
+    }
+}
+
+impl Chip8 {
+    /// Where programs are loaded and start running
+    pub(crate) fn start_address(&self) -> u16 {
+        match self.machine {
+            Machine::Chip8X => CHIP8X_START_ADDRESS,
+            _ => START_ADDRESS,
+        }
+    }
+
+    /// The width and height of the machine's display
+    pub(crate) fn display_size(&self) -> (usize, usize) {
+        match self.machine {
+            Machine::Hires => (HIRES_WIDTH, HIRES_HEIGHT),
+            _ => Display::default().size(),
+        }
+    }
+
+    /// How much memory the machine has to load a ROM of `rom_length` bytes into
+    pub(crate) fn memory_size(&self, rom_length: usize) -> usize {
+        match self.machine {
+            Machine::MegaChip => (START_ADDRESS as usize + rom_length).clamp(MEGACHIP_MIN_MEMORY_SIZE, MEGACHIP_MAX_MEMORY_SIZE),
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    /// The CHIP-8X colour overlay to draw the display with, for a CHIP-8X machine
+    pub fn colour_overlay(&self) -> Option<&ColourOverlay> {
+        match self.machine {
+            Machine::Chip8X => Some(&self.colours),
+            _ => None,
+        }
+    }
+
+    /// Execute the current opcode if it's one the machine's variant adds or changes.
+    /// Returns false if it's left to the CHIP-8 instruction set.
+    pub(crate) fn call_variant_op(&mut self) -> bool {
+        match self.machine {
+            Machine::Chip8X => self.call_chip8x_op(),
+            Machine::Chip8E => self.call_chip8e_op(),
+            Machine::Hires => self.call_hires_op(),
+            Machine::MegaChip => self.call_megachip_op(),
+            _ => false,
+        }
+    }
+
+    /// Play a frame's worth of any digitised sound MegaChip has playing
+    pub(crate) fn play_digitised_sound(&mut self) {
+        let mega = match &mut self.mega {
+            Some(mega) => mega,
+            None => return,
+        };
+        let sound = match &mut mega.sound {
+            Some(sound) => sound,
+            None => return,
+        };
+
+        let step = sound.rate as f64 / SAMPLE_RATE as f64;
+        for _ in 0..SAMPLE_RATE as u32 / FRAME_RATE {
+            if sound.position >= sound.length as f64 {
+                if !sound.looping || sound.length == 0 {
+                    mega.sound = None;
+                    return;
+                }
+                sound.position %= sound.length as f64;
+            }
+
+            let sample = self.memory[(sound.start + sound.position as usize) % self.memory.len()];
+            self.samples.push((sample as f32 - 128.0) / 128.0);
+            sound.position += step;
+        }
+    }
+
+    fn call_hires_op(&mut self) -> bool {
+        match self.opcode {
+            HIRES_ENTRY if self.pc == START_ADDRESS + 2 => self.pc = HIRES_START_ADDRESS,
+            HIRES_CLEAR => self.op_00e0(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn call_megachip_op(&mut self) -> bool {
+        if self.opcode == MEGACHIP_ON {
+            self.op_0011();
+            return true;
+        }
+        if self.mega.is_none() {
+            return false;
+        }
+
+        match (self.opcode >> 12, self.vx(), self.last_byte()) {
+            _ if self.opcode == MEGACHIP_OFF => self.op_0010(),
+            _ if self.opcode == 0x00E0 => self.op_00e0_megachip(),
+            (0x0, 0x0, n) if n >> 4 == 0xB => self.op_00bn(),
+            (0x0, 0x1, _) => self.op_01nn(),
+            (0x0, 0x2, _) => self.op_02nn(),
+            (0x0, 0x3, _) => self.op_03nn(),
+            (0x0, 0x4, _) => self.op_04nn(),
+            (0x0, 0x5, _) => self.op_05nn(),
+            (0x0, 0x6, _) => self.op_060n(),
+            (0x0, 0x7, _) => self.op_0700(),
+            (0x0, 0x8, _) => self.op_080n(),
+            (0x0, 0x9, _) => self.op_09nn(),
+            (0xD, _, _) => self.op_dxyn_megachip(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn call_chip8x_op(&mut self) -> bool {
+        match (self.opcode >> 12, self.opcode & 0x000F, self.last_byte()) {
+            _ if self.opcode == 0x02A0 => self.op_02a0(),
+            (0x5, 0x1, _) => self.op_5xy1_chip8x(),
+            (0xB, 0x0, _) => self.op_bxy0(),
+            (0xB, _, _) => self.op_bxyn(),
+            (0xE, _, 0xF2) => self.op_exf2(),
+            (0xE, _, 0xF5) => self.op_exf5(),
+            (0xF, _, 0xF8) => {}
+            (0xF, _, 0xFB) => self.op_fxfb(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn call_chip8e_op(&mut self) -> bool {
+        match (self.opcode >> 12, self.opcode & 0x000F, self.last_byte()) {
+            _ if self.opcode == 0x00ED => self.op_00ed(),
+            _ if self.opcode == 0x0151 => self.op_0151(),
+            _ if self.opcode == 0x0188 => self.skip_next_inst(),
+            (0x5, 0x1, _) => self.op_5xy1_chip8e(),
+            (0x5, 0x2, _) => self.op_5xy2(),
+            (0x5, 0x3, _) => self.op_5xy3(),
+            (0xB, _, _) if self.vx() == 0xB => self.op_bbnn(),
+            (0xB, _, _) if self.vx() == 0xF => self.op_bfnn(),
+            (0xF, _, 0x03) => {}
+            (0xF, _, 0x1B) => self.op_fx1b(),
+            (0xF, _, 0x4F) => self.op_fx4f(),
+            (0xF, _, 0xE3) | (0xF, _, 0xE7) => self.op_fxe7(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// MegaChip's state, its instructions only run while MegaChip mode is on
+    fn mega(&mut self) -> &mut MegaChip {
+        self.mega.as_mut().expect("MegaChip mode is off")
+    }
+
+    /// The byte `offset` bytes on from I
+    fn byte_at_index(&self, offset: usize) -> u8 {
+        self.memory[(self.index as usize + offset) % self.memory.len()]
+    }
+
+    fn op_0011(&mut self) {
+        // Switch MegaChip mode on, with its 256x192 colour display.
+        if self.mega.is_none() {
+            self.mega = Some(Box::default());
+            self.video = Display::coloured(MEGACHIP_WIDTH, MEGACHIP_HEIGHT);
+        }
+    }
+
+    fn op_0010(&mut self) {
+        // Switch MegaChip mode off, back to the CHIP-8 display.
+        self.mega = None;
+        let (width, height) = self.display_size();
+        self.video = Display::new(width, height);
+    }
+
+    fn op_00e0_megachip(&mut self) {
+        // Show what's been drawn, as opaque as the screen alpha says, then clear it to draw the next frame.
+        if let Some(mega) = &mut self.mega {
+            let alpha = mega.alpha as u32;
+            for (out, pixel) in self.video.iter_mut().zip(mega.buffer.iter()) {
+                let fade = |shift: u32| (((pixel >> shift) & 0xFF) * alpha / 0xFF) << shift;
+                *out = fade(24) | fade(16) | fade(8) | 0xFF;
+            }
+            mega.buffer.fill(0);
+            mega.indices.fill(0);
+        }
+    }
+
+    fn op_00bn(&mut self) {
+        // Scroll up n lines.
+        let lines = (self.opcode & 0x000F) as usize;
+        self.mega().scroll_up(lines);
+    }
+
+    fn op_01nn(&mut self) {
+        // Set I = nnnnnn, the next 2 bytes are its low 16 bits.
+        let low = (self.memory[self.pc as usize] as u32) << 8 | self.memory[self.pc as usize + 1] as u32;
+        self.index = (self.last_byte() as u32) << 16 | low;
+        self.skip_next_inst();
+    }
+
+    fn op_02nn(&mut self) {
+        // Load nn colours into the palette from colour 1 on, 4 bytes of ARGB each starting at I.
+        let colours: Vec<u32> = (0..self.last_byte() as usize)
+            .map(|colour| u32::from_be_bytes([0, 1, 2, 3].map(|i| self.byte_at_index(colour * 4 + i))).rotate_left(8))
+            .collect();
+        self.mega().palette[1..].iter_mut().zip(colours).for_each(|(out, colour)| *out = colour);
+    }
+
+    fn op_03nn(&mut self) {
+        // Set the sprite width to nn, 0 is 256.
+        let width = self.last_byte() as usize;
+        self.mega().sprite_width = if width == 0 { 256 } else { width };
+    }
+
+    fn op_04nn(&mut self) {
+        // Set the sprite height to nn, 0 is 256.
+        let height = self.last_byte() as usize;
+        self.mega().sprite_height = if height == 0 { 256 } else { height };
+    }
+
+    fn op_05nn(&mut self) {
+        // Set the screen alpha to nn.
+        self.mega().alpha = self.last_byte();
+    }
+
+    fn op_060n(&mut self) {
+        // Play the digitised sound at I, looping if n is 0 and once if it's 1.
+        let rate = u16::from_be_bytes([self.byte_at_index(0), self.byte_at_index(1)]) as u32;
+        let length = u32::from_be_bytes([0, self.byte_at_index(2), self.byte_at_index(3), self.byte_at_index(4)]) as usize;
+        let start = self.index as usize + SOUND_HEADER_SIZE;
+        let looping = self.opcode & 0x000F == 0;
+        self.mega().sound = Some(DigitisedSound { start, length, rate, looping, position: 0.0 });
+    }
+
+    fn op_0700(&mut self) {
+        // Stop the digitised sound.
+        self.mega().sound = None;
+    }
+
+    fn op_080n(&mut self) {
+        // Set the blend mode: normal, 25%, 50%, add or multiply.
+        if let Some(blend) = Blend::from_code((self.opcode & 0x000F) as u8) {
+            self.mega().blend = blend;
+        }
+    }
+
+    fn op_09nn(&mut self) {
+        // Set the collision colour to palette index nn.
+        self.mega().collision_colour = Some(self.last_byte());
+    }
+
+    fn op_dxyn_megachip(&mut self) {
+        // Draw a sprite of the sprite width and height from I at (Vx, Vy), set VF = collision.
+        // Each byte is a pixel's palette index, those of 0 are left out.
+        let (x, y) = (self.registers[self.vx()] as usize, self.registers[self.vy()] as usize);
+        let size = self.mega().sprite_width * self.mega().sprite_height;
+        let sprite: Vec<u8> = (0..size).map(|i| self.byte_at_index(i)).collect();
+        let collision = self.mega().draw(x, y, &sprite);
+        self.registers[0xF] = collision as u8;
+    }
+
+    fn op_02a0(&mut self) {
+        // Step the background colour on to the next one.
+        self.colours.next_background();
+    }
+
+    fn op_5xy1_chip8x(&mut self) {
+        // Vx = Vx + Vy, adding each nibble separately without carrying between them.
+        let (a, b) = (self.registers[self.vx()], self.registers[self.vy()]);
+        self.registers[self.vx()] = (a & 0xF0).wrapping_add(b & 0xF0) | a.wrapping_add(b) & 0x0F;
+    }
+
+    fn op_bxy0(&mut self) {
+        // Colour blocks 8 pixels wide and 4 high in colour Vy. The low nibbles of Vx and Vx+1 are
+        // the left column and top row of blocks, the high nibbles how many more to colour.
+        let horizontal = self.registers[self.vx()] as usize;
+        let vertical = self.registers[(self.vx() + 1) % 16] as usize;
+        let columns = (horizontal & 0xF)..(horizontal & 0xF) + (horizontal >> 4) + 1;
+        let rows = (vertical & 0xF) * BLOCK_HEIGHT..((vertical & 0xF) + (vertical >> 4) + 1) * BLOCK_HEIGHT;
+        self.colours.fill(columns, rows, self.registers[self.vy()]);
+    }
+
+    fn op_bxyn(&mut self) {
+        // Colour n rows of the zone containing pixel (Vx, Vx+1) in colour Vy.
+        let column = self.registers[self.vx()] as usize % LORES_WIDTH / ZONE_WIDTH;
+        let row = self.registers[(self.vx() + 1) % 16] as usize % LORES_HEIGHT;
+        let n = (self.opcode & 0x000F) as usize;
+        self.colours.fill(column..column + 1, row..row + n, self.registers[self.vy()]);
+    }
+
+    fn op_exf2(&mut self) {
+        // Skip next instruction if key Vx is pressed on the second keypad.
+        let key = self.registers[self.vx()] as usize;
+        if self.second_keypad[key] != 0 {
+            self.skip_next_inst();
+        }
+    }
+
+    fn op_exf5(&mut self) {
+        // Skip next instruction if key Vx is not pressed on the second keypad.
+        let key = self.registers[self.vx()] as usize;
+        if self.second_keypad[key] == 0 {
+            self.skip_next_inst();
+        }
+    }
+
+    fn op_fxfb(&mut self) {
+        // Wait for input on port 3 and store it in Vx. Nothing's connected, so it reads 0.
+        self.registers[self.vx()] = 0;
+    }
+
+    fn op_00ed(&mut self) {
+        // Stop the program.
+        self.repeat_last_inst();
+    }
+
+    fn op_0151(&mut self) {
+        // Wait until the delay timer reaches 0.
+        if self.delay_timer > 0 {
+            self.repeat_last_inst();
+        }
+    }
+
+    fn op_5xy1_chip8e(&mut self) {
+        // Skip next instruction if Vx > Vy.
+        if self.registers[self.vx()] > self.registers[self.vy()] {
+            self.skip_next_inst();
+        }
+    }
+
+    fn op_5xy2(&mut self) {
+        // Store registers Vx to Vy in memory starting at location I, then move I past them.
+        let (x, y) = (self.vx(), self.vy());
+        let size = self.memory.len();
+        for register in x..=y {
+            self.memory[(self.index as usize + register - x) % size] = self.registers[register];
+        }
+        self.index += (y + 1).saturating_sub(x) as u32;
+    }
+
+    fn op_5xy3(&mut self) {
+        // Read registers Vx to Vy from memory starting at location I, then move I past them.
+        let (x, y) = (self.vx(), self.vy());
+        for register in x..=y {
+            self.registers[register] = self.memory[(self.index as usize + register - x) % self.memory.len()];
+        }
+        self.index += (y + 1).saturating_sub(x) as u32;
+    }
+
+    fn op_bbnn(&mut self) {
+        // Jump back nn bytes from this instruction.
+        self.pc = self.pc.wrapping_sub(2).wrapping_sub(self.last_byte() as u16);
+    }
+
+    fn op_bfnn(&mut self) {
+        // Jump forward nn bytes from this instruction.
+        self.pc = self.pc.wrapping_sub(2).wrapping_add(self.last_byte() as u16);
+    }
+
+    fn op_fx1b(&mut self) {
+        // Skip Vx bytes.
+        self.pc = self.pc.wrapping_add(self.registers[self.vx()] as u16);
+    }
+
+    fn op_fx4f(&mut self) {
+        // Set the delay timer to Vx and wait until it reaches 0.
+        if !self.waiting_for_timer {
+            self.delay_timer = self.registers[self.vx()];
+            self.waiting_for_timer = true;
+        }
+
+        if self.delay_timer > 0 {
+            self.repeat_last_inst();
+        } else {
+            self.waiting_for_timer = false;
+        }
+    }
+
+    fn op_fxe7(&mut self) {
+        // Read port 3 into Vx, FXE3 waits for a strobe first. Nothing's connected, so it reads 0.
+        self.registers[self.vx()] = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8(machine: Machine, rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.machine = machine;
+        chip8.load_bytes(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn it_parses_machines() {
+        assert_eq!("chip8x".parse::<Machine>(), Ok(Machine::Chip8X));
+        assert_eq!(Machine::Chip8E.to_string(), "chip8e");
+        assert!("schip".parse::<Machine>().is_err());
+    }
+
+    #[test]
+    fn it_starts_chip8x_programs_at_0300() {
+        let chip8 = chip8(Machine::Chip8X, &[0x12, 0x00]);
+        assert_eq!(chip8.pc, 0x300);
+        assert_eq!(chip8.memory[0x300], 0x12);
+        assert!(chip8.colour_overlay().is_some());
+        assert!(Chip8::new().colour_overlay().is_none());
+    }
+
+    #[test]
+    fn it_colours_the_display_on_chip8x() {
+        // V0 = 0x11, V1 = 0x00, V2 = 4, colour 2 columns and 4 rows of 8x4 blocks green
+        let mut chip8 = chip8(Machine::Chip8X, &[0x60, 0x11, 0x61, 0x00, 0x62, 0x04, 0xB0, 0x20]);
+        (0..4).for_each(|_| chip8.cycle());
+
+        let overlay = chip8.colour_overlay().unwrap();
+        assert_eq!(overlay.palette(8, 0).foreground, COLOURS[4]);
+        assert_eq!(overlay.palette(23, 3).foreground, COLOURS[4]);
+        assert_eq!(overlay.palette(24, 0).foreground, COLOURS[RED as usize]);
+        assert_eq!(overlay.palette(8, 4).foreground, COLOURS[RED as usize]);
+
+        // V2 = 7, colour 3 rows of the zone with pixel (V0, V1) = (17, 0) white, then change the background
+        chip8.load_bytes(&[0x62, 0x07, 0xB0, 0x23, 0x02, 0xA0]).unwrap();
+        (0..3).for_each(|_| chip8.cycle());
+
+        let overlay = chip8.colour_overlay().unwrap();
+        assert_eq!(overlay.palette(16, 2).foreground, COLOURS[7]);
+        assert_eq!(overlay.palette(16, 3).foreground, COLOURS[4]);
+        assert_eq!(overlay.palette(0, 0).background, BACKGROUNDS[1]);
+    }
+
+    #[test]
+    fn it_adds_nibbles_separately_on_chip8x() {
+        let mut chip8 = chip8(Machine::Chip8X, &[0x60, 0x9C, 0x61, 0x78, 0x50, 0x11]);
+        (0..3).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.registers[0], 0x04);
+    }
+
+    #[test]
+    fn it_reads_the_second_keypad_on_chip8x() {
+        // V0 = 0xA, skip if key A on the second keypad is pressed, then if it isn't
+        let mut chip8 = chip8(Machine::Chip8X, &[0x60, 0x0A, 0xE0, 0xF2]);
+        chip8.keypad[0xA] = 1;
+        (0..2).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.pc, 0x304);
+
+        chip8.second_keypad[0xA] = 1;
+        chip8.load_bytes(&[0x60, 0x0A, 0xE0, 0xF2]).unwrap();
+        (0..2).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.pc, 0x306);
+
+        chip8.load_bytes(&[0x60, 0x0A, 0xE0, 0xF5]).unwrap();
+        (0..2).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.pc, 0x304);
+        chip8.second_keypad[0xA] = 0;
+        chip8.load_bytes(&[0x60, 0x0A, 0xE0, 0xF5]).unwrap();
+        (0..2).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.pc, 0x306);
+    }
+
+    #[test]
+    fn it_runs_hires_roms_on_a_64x64_display() {
+        let mut rom = vec![0; 0xC4];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        // V1 = 40, draw the font's 0 at (V0, V1) = (0, 40), then clear the screen
+        rom[0xC0..].copy_from_slice(&[0x61, 0x28, 0xD0, 0x15]);
+        rom.extend([0x02, 0x30]);
+        // A CHIP-8 ROM could start by jumping over data to 0260 too, so it's never guessed
+        assert_eq!(detect(&rom), None);
+        assert_eq!(detect(&[0x12, 0x60, 0x00, 0x00]), None);
+
+        let mut chip8 = chip8(Machine::Hires, &rom);
+        assert_eq!(chip8.video.size(), (64, 64));
+        chip8.index = 0x50;
+        chip8.cycle();
+        assert_eq!(chip8.pc, 0x2C0);
+
+        chip8.cycle();
+        chip8.cycle();
+        assert_eq!(chip8.video[40 * 64], 0xFFFFFFFF);
+
+        chip8.cycle();
+        assert!(chip8.video.iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn it_draws_blended_sprites_on_megachip() {
+        let mut rom = vec![
+            0x00, 0x11, 0x01, 0x00, 0x03, 0x00, 0x02, 0x02, 0x01, 0x00, 0x03, 0x08, 0x03, 0x02, 0x04, 0x01,
+            0x60, 0x10, 0x61, 0x11, 0x09, 0x02, 0xD0, 0x01, 0x08, 0x02, 0xD1, 0x01, 0x00, 0xE0,
+        ];
+        rom.resize(0x100, 0);
+        // Red and blue, then a sprite 2 pixels wide of them
+        rom.extend([0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x01, 0x02]);
+        assert_eq!(detect(&rom), Some(Machine::MegaChip));
+
+        let mut chip8 = chip8(Machine::MegaChip, &rom);
+        assert_eq!(chip8.memory.len(), 0x10000);
+        chip8.cycle();
+        assert_eq!(chip8.video.size(), (256, 192));
+        assert!(chip8.video.is_coloured());
+
+        // Draw at (16, 16), with the collision colour set to blue
+        (0..9).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.index, 0x308);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        // Then at (17, 16) 50% blended, the red pixel goes over the blue one
+        (0..2).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.registers[0xF], 1);
+        assert!(chip8.video.iter().all(|p| *p == 0));
+
+        chip8.cycle();
+        let row = &chip8.video[16 * 256..17 * 256];
+        assert_eq!(row[15..19], [0x000000FF, 0xFF0000FF, 0x7F007FFF, 0x00007FFF]);
+    }
+
+    #[test]
+    fn it_plays_digitised_sound_on_megachip() {
+        // Play 3 samples at 22050 Hz once, then forever
+        let mut rom = vec![0x00, 0x11, 0x01, 0x00, 0x03, 0x00, 0x06, 0x01, 0x12, 0x08];
+        rom.resize(0x100, 0);
+        rom.extend([0x56, 0x22, 0x00, 0x00, 0x03, 0x00, 0x80, 0xFF, 0x00]);
+
+        let mut chip8 = chip8(Machine::MegaChip, &rom);
+        chip8.run_frame(5);
+        assert_eq!(chip8.samples(), [0.0, 0.0, 127.0 / 128.0, 127.0 / 128.0, -1.0, -1.0]);
+        chip8.run_frame(5);
+        assert!(chip8.samples().is_empty());
+
+        chip8.load_bytes(&[0x06, 0x00, 0x12, 0x02]).unwrap();
+        chip8.run_frame(5);
+        assert_eq!(chip8.samples().len(), 735);
+        assert_eq!(chip8.samples()[6], 0.0);
+    }
+
+    #[test]
+    fn it_runs_chip8e_instructions() {
+        // Save V0-V2 at I, load them into V3-V5, then jump forward over a stop and back to it
+        let mut chip8 = chip8(Machine::Chip8E, &[
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA4, 0x00,
+            0x50, 0x22, 0xA4, 0x00, 0x53, 0x53,
+            0xBF, 0x04, 0x00, 0xED, 0xBB, 0x02,
+        ]);
+        (0..7).for_each(|_| chip8.cycle());
+        assert_eq!(chip8.registers[3..6], [0x01, 0x02, 0x03]);
+        assert_eq!(chip8.index, 0x403);
+
+        chip8.cycle();
+        assert_eq!(chip8.pc, 0x212);
+        chip8.cycle();
+        assert_eq!(chip8.pc, 0x210);
+
+        // 00ED stops
+        chip8.cycle();
+        assert_eq!(chip8.pc, 0x210);
+    }
+
+    #[test]
+    fn it_waits_for_the_delay_timer_on_chip8e() {
+        let mut chip8 = chip8(Machine::Chip8E, &[0x60, 0x02, 0xF0, 0x4F, 0x12, 0x04]);
+        chip8.run_frame(5);
+        assert_eq!(chip8.pc, 0x202);
+        chip8.run_frame(5);
+        assert_eq!(chip8.pc, 0x202);
+        chip8.run_frame(5);
+        assert_eq!(chip8.pc, 0x204);
+    }
+}
@@ -1,46 +1,548 @@
-use std::env;
-use std::process::exit;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
 use std::time::Instant;
 
-use chip8_rs::{Chip8, VIDEO_HEIGHT, VIDEO_WIDTH};
-use chip8_rs::platform::Platform;
+use clap::Parser;
+
+use chip8_rs::{Chip8, rom_hash};
+use chip8_rs::audio::{SAMPLE_RATE, Tone};
+use chip8_rs::config::{Config, Settings};
+use chip8_rs::disasm::disassemble;
+use chip8_rs::display::Display;
+use chip8_rs::machine::{self, Machine};
+use chip8_rs::menu::{self, Menu};
+use chip8_rs::movie::{Input, Movie, MovieHeader, MovieWriter};
+use chip8_rs::platform::{self, Action, Indicator, MenuAction, Platform};
+use chip8_rs::rewind::Rewind;
+use chip8_rs::romdb::{RomDatabase, RomInfo};
+use chip8_rs::timing::{self, FRAME_RATE, FrameLimiter};
+use chip8_rs::watch::FileWatcher;
+use chip8_rs::wav::{self, AudioRecorder, SampleWriter};
+use chip8_rs::window::WindowState;
+
+use crate::cli::{Cli, Command, RunArgs};
+
+mod cli;
+
+const DEFAULT_SCALE: u32 = 10;
+const DEFAULT_CYCLES_PER_FRAME: usize = 10;
+/// Frames run for each one presented while fast forwarding
+const FAST_FORWARD_FRAMES: usize = 4;
+/// Frames between the snapshots kept for rewinding, each step back goes this far
+const REWIND_INTERVAL: u32 = 4;
+/// How far back rewinding can go
+const REWIND_SECONDS: u32 = 30;
+
+type Recorder = AudioRecorder<Box<dyn SampleWriter>>;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Some(Command::Run(args)) => run(&args),
+        Some(Command::Disasm { rom }) => disasm(&rom),
+        Some(Command::Info { rom, romdb }) => info(&rom, romdb),
+        None => run(&cli.run),
+    };
 
-    if args.len() != 4 {
-        eprintln!("Usage: {} <Scale> <Delay> <ROM>", args[0]);
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
         exit(1);
     }
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("could not read ROM '{}': {}", path.display(), e))
+}
+
+fn disasm(path: &Path) -> Result<(), String> {
+    let rom = read_rom(path)?;
+    disassemble(&rom, 0x200).iter().for_each(|line| println!("{}", line));
+    Ok(())
+}
+
+fn load_config(path: Option<PathBuf>) -> Result<Config, String> {
+    match path.or_else(Config::default_path) {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// Use the database from the given directory, or the user data directory, or the built-in one
+fn load_database(dir: Option<PathBuf>) -> Result<RomDatabase, String> {
+    if let Some(dir) = dir {
+        return RomDatabase::load_dir(&dir);
+    }
+
+    match RomDatabase::default_dir() {
+        Some(dir) if dir.exists() => RomDatabase::load_dir(&dir),
+        _ => Ok(RomDatabase::builtin()),
+    }
+}
+
+fn info(path: &Path, romdb: Option<PathBuf>) -> Result<(), String> {
+    let database = load_database(romdb)?;
+    let info = database.lookup(&rom_hash(&read_rom(path)?));
+
+    let unknown = || "unknown".to_string();
+    println!("SHA-1:    {}", info.hash);
+    println!("Title:    {}", info.title.clone().unwrap_or_else(unknown));
+    println!("Platform: {}", info.platform.clone().unwrap_or_else(unknown));
+    println!("Quirks:   {}", info.quirks.map(|q| q.to_string()).unwrap_or_else(unknown));
+    println!("Tickrate: {}", info.tickrate.map(|t| t.to_string()).unwrap_or_else(unknown));
+    info.keys.iter().for_each(|(action, key)| println!("Key {:X}:    {}", key, action));
+    Ok(())
+}
+
+/// A ROM and the settings to run it with
+struct Game {
+    path: PathBuf,
+    rom: Vec<u8>,
+    info: RomInfo,
+    settings: Settings,
+    /// The original CHIP-8 interpreter, for the VIP machine
+    interpreter: Option<Vec<u8>>,
+}
+
+impl Game {
+    fn load(path: &Path, config: &Config, database: &RomDatabase, args: &RunArgs) -> Result<Game, String> {
+        let rom = read_rom(path)?;
+        let info = database.lookup(&rom_hash(&rom));
+
+        // The database's recommendations, overridden by the config file, overridden by the command line
+        let mut settings = info.settings()
+            .merge(&config.settings_for(&info.hash))
+            .merge(&args.settings());
+        // Some ROMs can be recognised as needing a variant, unless one was picked
+        if settings.machine.is_none() {
+            settings.machine = machine::detect(&rom);
+        }
+
+        let interpreter = match args.vip_interpreter.as_ref().or(config.vip_interpreter.as_ref()) {
+            Some(path) => Some(fs::read(path).map_err(|e| format!("could not read interpreter '{}': {}", path.display(), e))?),
+            None => None,
+        };
+
+        Ok(Game { path: path.to_path_buf(), rom, info, settings, interpreter })
+    }
+
+    /// Read the ROM again after it's been changed, keeping the settings it was loaded with
+    fn reload(&mut self) -> Result<(), String> {
+        self.rom = read_rom(&self.path)?;
+        Ok(())
+    }
+
+    /// A freshly reset machine with the ROM loaded
+    fn boot(&self, seed: Option<u64>) -> Result<Chip8, String> {
+        let mut chip8 = Chip8::new();
+        if let Some(seed) = seed {
+            chip8.set_seed(seed);
+        }
+        chip8.apply_settings(&self.settings);
+        if chip8.machine == Machine::Vip {
+            let interpreter = self.interpreter.as_ref().ok_or("the vip machine needs the original interpreter, see --vip-interpreter")?;
+            chip8.use_vip(interpreter).map_err(|e| format!("could not load interpreter: {}", e))?;
+        }
+        chip8.load_bytes(&self.rom).map_err(|e| format!("could not load ROM '{}': {}", self.path.display(), e))?;
+        Ok(chip8)
+    }
+
+    fn cycles_per_frame(&self) -> usize {
+        self.settings.cycles_per_frame.unwrap_or(DEFAULT_CYCLES_PER_FRAME)
+    }
+
+    /// The ROM's title if it's in the database, otherwise its file name
+    fn name(&self) -> String {
+        match &self.info.title {
+            Some(title) => title.clone(),
+            None => self.path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        }
+    }
+}
 
-    let video_scale: u32 = args[1].parse().expect("<Scale> must be an integer");
-    let cycle_delay: u128 = args[2].parse().expect("<Delay> must be an integer");
-    let rom_filename= &args[3];
+fn window_title(game: Option<&Game>) -> String {
+    match game.and_then(|game| game.info.title.as_ref()) {
+        Some(title) => format!("CHIP-8 Emulator - {}", title),
+        None => "CHIP-8 Emulator".to_string(),
+    }
+}
+
+/// Apply the settings which can change from one ROM to the next
+fn apply_settings(platform: &mut Platform, settings: &Settings) -> Result<(), String> {
+    platform.set_render_mode(settings.phosphor.unwrap_or_default());
+    platform.set_palette(settings.palette.unwrap_or_default());
+    platform.set_integer_scaling(settings.integer_scaling.unwrap_or(false));
+    platform.hud().set_quirks(&settings.quirks.unwrap_or_default());
+    platform.set_muted(settings.audio.mute.unwrap_or(false));
+    platform.set_tone(settings.audio.tone());
+    platform.set_key_bindings(&settings.key_bindings()?, &settings.second_key_bindings()?)
+}
+
+fn run(args: &RunArgs) -> Result<(), String> {
+    let config = load_config(args.config.clone())?;
+    let database = load_database(args.romdb.clone().or_else(|| config.romdb.clone()))?;
+
+    let mut game = match &args.rom {
+        Some(path) => Some(Game::load(path, &config, &database, args)?),
+        None => None,
+    };
+    let (mut input, seed) = open_movie(args, game.as_mut())?;
+    let settings = match &game {
+        Some(game) => game.settings.clone(),
+        None => config.defaults.merge(&args.settings()),
+    };
+
+    let mut recorder = match &args.record_audio {
+        Some(path) => Some(create_recorder(path, settings.audio.tone())?),
+        None => None,
+    };
+
+    if args.headless {
+        let game = game.ok_or("no ROM given, see --help for usage")?;
+        let frames = match &input {
+            Input::Playback { frames, .. } => frames.len() as u64,
+            _ => args.frames,
+        };
+        run_headless(&mut game.boot(seed)?, game.cycles_per_frame(), frames, &mut input, &mut recorder)?;
+        finish_movie(&mut input)?;
+        return finish_recording(recorder);
+    }
+
+    // Reopen the window where it was last time, unless a scale was asked for. Displays of other
+    // sizes are fitted into it.
+    let (display_width, display_height) = Display::default().size();
+    let window_state_path = WindowState::default_path();
+    let saved_window = window_state_path.as_deref().and_then(WindowState::load);
+    let (window_width, window_height) = match (settings.scale, saved_window) {
+        (None, Some(state)) => (state.width, state.height),
+        (scale, _) => {
+            let scale = scale.unwrap_or(DEFAULT_SCALE);
+            (display_width as u32 * scale, display_height as u32 * scale)
+        }
+    };
 
     let (context, canvas, texture_creator) = Platform::create_window_canvas_texture_creator(
-        "CHIP-8 Emulator", VIDEO_WIDTH as u32 * video_scale, VIDEO_HEIGHT as u32 * video_scale);
-    let mut platform = Platform::new(context, canvas, &texture_creator, VIDEO_WIDTH as u32, VIDEO_HEIGHT as u32);
+        &window_title(game.as_ref()),
+        window_width,
+        window_height,
+        saved_window.map(|state| (state.x, state.y)),
+        !args.no_vsync,
+        args.fullscreen,
+    );
+    let audio = platform::open_audio(&context, &settings.audio.backend.clone().unwrap_or_default())?;
+    let mut platform = Platform::new(context, canvas, &texture_creator, display_width as u32, display_height as u32, audio);
+    platform.hud().set_visible(settings.hud.unwrap_or(false));
+    apply_settings(&mut platform, &settings)?;
+
+    let mut menu = match args.rom_dir.as_ref().or(config.rom_dir.as_ref()) {
+        Some(dir) => Menu::new(menu::scan(dir, &database)?),
+        None => Menu::new(Vec::new()),
+    };
+    let mut menu_open = false;
 
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(&rom_filename);
+    // Without a ROM there's nothing to run until one is picked from the menu or dropped on the window
+    let mut chip8 = match &game {
+        Some(game) => game.boot(seed)?,
+        None if !menu.is_empty() => {
+            menu_open = true;
+            Chip8::new()
+        }
+        None => {
+            platform.hud().set_prompt(Some("Drop a ROM here".to_string()));
+            Chip8::new()
+        }
+    };
+    let mut cycles_per_frame = game.as_ref().map_or(DEFAULT_CYCLES_PER_FRAME, Game::cycles_per_frame);
 
+    let watch = args.watch || args.watch_source.is_some();
+    let mut rom_watcher = game.as_ref().filter(|_| watch).map(|game| FileWatcher::new(&game.path));
+    let mut source_watcher = args.watch_source.as_ref().map(FileWatcher::new);
 
-    let video_pitch = std::mem::size_of::<u32>() * VIDEO_WIDTH;
+    let mut muted = settings.audio.mute.unwrap_or(false);
 
-    let mut last_cycle_time = Instant::now();
+    let mut frame_limiter = FrameLimiter::new(FRAME_RATE);
     let mut quit = false;
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut keys = [0; 16];
+    let mut keys2 = [0; 16];
+    let mut rewinding = false;
+    let mut rewind = Rewind::new((REWIND_SECONDS * FRAME_RATE / REWIND_INTERVAL) as usize, REWIND_INTERVAL);
 
     while !quit {
-        quit = platform.process_input(&mut chip8.keypad);
+        for action in platform.process_input(&mut keys, &mut keys2) {
+            // Picking a ROM from the menu loads it just like dropping it on the window
+            let action = match action {
+                Action::Menu(MenuAction::Select) => match menu.selected() {
+                    Some(entry) => {
+                        menu_open = false;
+                        Action::LoadRom(entry.path.clone())
+                    }
+                    None => continue,
+                },
+                action => action,
+            };
+
+            match action {
+                // Anything that changes the machine other than through the keypad would stop the movie replaying the same
+                Action::Reset | Action::SpeedUp | Action::SpeedDown | Action::LoadRom(_) | Action::Rewind(true) if input.is_movie() => {
+                    platform.hud().show_message("Not available during a movie", Instant::now());
+                }
+                Action::Quit => quit = true,
+                Action::TogglePause => {
+                    paused = !paused;
+                    platform.hud().show_message(if paused { "Paused" } else { "Resumed" }, Instant::now());
+                }
+                Action::Reset => {
+                    if let Some(game) = &game {
+                        chip8.replace(game.boot(seed)?);
+                        rewind.clear();
+                        platform.hud().show_message("Reset", Instant::now());
+                    }
+                }
+                Action::SpeedUp | Action::SpeedDown if chip8.fixed_speed() => {
+                    platform.hud().show_message("Speed fixed by VIP timing", Instant::now());
+                }
+                Action::SpeedUp | Action::SpeedDown => {
+                    cycles_per_frame = match action {
+                        Action::SpeedUp => timing::faster(cycles_per_frame),
+                        _ => timing::slower(cycles_per_frame),
+                    };
+                    let message = format!("Speed {} instructions per frame", cycles_per_frame);
+                    platform.hud().show_message(message, Instant::now());
+                }
+                Action::LoadRom(path) => {
+                    // A file that isn't a usable ROM leaves the current one running
+                    let loaded = Game::load(&path, &config, &database, args)
+                        .and_then(|game| Ok((game.boot(seed)?, game)));
+                    match loaded {
+                        Ok((new_chip8, new_game)) => {
+                            apply_settings(&mut platform, &new_game.settings)?;
+                            platform.set_title(&window_title(Some(&new_game)));
+                            platform.hud().set_prompt(None);
+                            platform.hud().show_message(format!("Loaded {}", new_game.name()), Instant::now());
+                            if watch {
+                                rom_watcher = Some(FileWatcher::new(&new_game.path));
+                            }
+
+                            chip8.replace(new_chip8);
+                            rewind.clear();
+                            cycles_per_frame = new_game.cycles_per_frame();
+                            muted = new_game.settings.audio.mute.unwrap_or(false);
+                            paused = false;
+                            game = Some(new_game);
+                        }
+                        Err(e) => {
+                            eprintln!("error: {}", e);
+                            platform.hud().show_message("Could not load ROM", Instant::now());
+                        }
+                    }
+                }
+                Action::ToggleMenu if menu.is_empty() => {
+                    platform.hud().show_message("No ROMs, see --rom-dir", Instant::now());
+                }
+                Action::ToggleMenu => menu_open = !menu_open,
+                Action::Menu(action) => match action {
+                    MenuAction::Up => menu.up(),
+                    MenuAction::Down => menu.down(),
+                    MenuAction::PageUp => menu.page_up(),
+                    MenuAction::PageDown => menu.page_down(),
+                    MenuAction::Select | MenuAction::Close => menu_open = false,
+                },
+                Action::FastForward(held) => fast_forward = held,
+                Action::Rewind(held) => rewinding = held,
+            }
+        }
+
+        let now = Instant::now();
+        if source_watcher.as_mut().map_or(false, |watcher| watcher.poll(now)) {
+            if let Some(command) = &args.build_command {
+                if let Err(e) = build(command) {
+                    eprintln!("error: {}", e);
+                    platform.hud().show_message("Build failed", now);
+                }
+            }
+        }
+
+        // Reloading keeps everything but the machine itself, so the speed, window and bindings stay as they were
+        if let (Some(game), Some(watcher)) = (&mut game, &mut rom_watcher) {
+            if watcher.poll(now) {
+                // Like resetting, reloading would stop the movie replaying the same
+                if input.is_movie() {
+                    platform.hud().show_message("Not reloading during a movie", now);
+                } else {
+                    match game.reload().and_then(|()| game.boot(seed)) {
+                        Ok(new_chip8) => {
+                            chip8.replace(new_chip8);
+                            rewind.clear();
+                            platform.hud().show_message(format!("Reloaded {}", game.name()), now);
+                        }
+                        Err(e) => {
+                            eprintln!("error: {}", e);
+                            platform.hud().show_message("Could not reload ROM", now);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The game stops while the menu is open
+        platform.set_menu_open(menu_open);
+        platform.hud().set_menu(if menu_open { Some(menu.page()) } else { None });
+        platform.set_muted(muted || paused || menu_open || rewinding);
+
+        let (frames, indicator) = match (paused, fast_forward) {
+            _ if game.is_none() || menu_open => (0, Indicator::None),
+            _ if rewinding => {
+                if let Some(snapshot) = rewind.step_back() {
+                    chip8.restore(&snapshot);
+                }
+                (0, Indicator::Rewind)
+            }
+            (true, _) => (0, Indicator::Paused),
+            (false, true) => (FAST_FORWARD_FRAMES, Indicator::FastForward),
+            (false, false) => (1, Indicator::None),
+        };
+        platform.set_indicator(indicator);
 
-        let current_time = Instant::now();
-        let dt = last_cycle_time.elapsed().as_millis();
+        for _ in 0..frames {
+            input.next_frame(&keys, &keys2, &mut chip8.keypad, &mut chip8.second_keypad).map_err(|e| format!("could not record movie: {}", e))?;
+            let executed = chip8.run_frame(cycles_per_frame);
+            rewind.record(&chip8);
+            platform.hud().count_instructions(executed);
+            platform.play_sound(chip8.sound_events(), chip8.samples()).map_err(|e| format!("could not play audio: {}", e))?;
+            record_frame(&mut recorder, &chip8)?;
+        }
+        platform.update(&chip8.video, chip8.colour_overlay());
+
+        if input.finished() {
+            platform.hud().show_message("Movie finished", Instant::now());
+            input = Input::Live;
+        }
+
+        // Presenting already blocked until the next refresh if vsync is available
+        if !platform.vsync() {
+            frame_limiter.wait();
+        }
+    }
+
+    platform.finish_sound().map_err(|e| format!("could not play audio: {}", e))?;
+    finish_movie(&mut input)?;
 
-        if dt > cycle_delay {
-            last_cycle_time = current_time;
-            chip8.cycle();
-            platform.update(&chip8.video, video_pitch, &chip8.sound_state());
+    if let (Some(path), Some(state)) = (window_state_path, platform.window_state()) {
+        if let Err(e) = state.save(&path) {
+            eprintln!("warning: could not save the window position: {}", e);
         }
     }
-    exit(0);
+
+    finish_recording(recorder)
+}
+
+/// Set up recording or playing back a movie, if one was asked for, and pick the random seed.
+/// A movie is played back with the quirks, timing, speed and seed it was recorded with.
+fn open_movie(args: &RunArgs, game: Option<&mut Game>) -> Result<(Input, Option<u64>), String> {
+    if args.play_movie.is_none() && args.record_movie.is_none() {
+        return Ok((Input::Live, args.seed));
+    }
+    let game = game.ok_or("a movie needs a ROM, see --help for usage")?;
+
+    if let Some(path) = &args.play_movie {
+        let movie = Movie::load(path).map_err(|e| format!("could not read movie '{}': {}", path.display(), e))?;
+        if movie.header.rom_hash != game.info.hash {
+            return Err(format!("movie '{}' was recorded with a different ROM, SHA-1 {}", path.display(), movie.header.rom_hash));
+        }
+        if let Some(hash) = &movie.header.interpreter_hash {
+            if game.interpreter.as_deref().map(rom_hash).as_ref() != Some(hash) {
+                return Err(format!("movie '{}' was recorded with a different VIP interpreter, SHA-1 {}", path.display(), hash));
+            }
+        }
+
+        game.settings.quirks = Some(movie.header.quirks);
+        game.settings.timing = Some(movie.header.timing);
+        game.settings.machine = Some(movie.header.machine);
+        game.settings.cycles_per_frame = Some(movie.header.cycles_per_frame);
+        return Ok((Input::Playback { frames: movie.frames, position: 0 }, Some(movie.header.seed)));
+    }
+
+    match &args.record_movie {
+        Some(path) => {
+            // The movie can only be replayed exactly with a known seed, so pick one if none was given
+            let seed = args.seed.unwrap_or_else(rand::random);
+            let header = MovieHeader {
+                seed,
+                quirks: game.settings.quirks.unwrap_or_default(),
+                timing: game.settings.timing.unwrap_or_default(),
+                machine: game.settings.machine.unwrap_or_default(),
+                rom_hash: game.info.hash.clone(),
+                interpreter_hash: match game.settings.machine {
+                    Some(Machine::Vip) => game.interpreter.as_deref().map(rom_hash),
+                    _ => None,
+                },
+                cycles_per_frame: game.cycles_per_frame(),
+            };
+            let writer = MovieWriter::create(path, &header)
+                .map_err(|e| format!("could not create '{}': {}", path.display(), e))?;
+            Ok((Input::Record(writer), Some(seed)))
+        }
+        None => Ok((Input::Live, args.seed)),
+    }
+}
+
+fn finish_movie(input: &mut Input) -> Result<(), String> {
+    input.finish().map_err(|e| format!("could not record movie: {}", e))
+}
+
+/// Run the command that builds the ROM from its source through the shell
+fn build(command: &str) -> Result<(), String> {
+    let status = if cfg!(windows) {
+        process::Command::new("cmd").args(["/C", command]).status()
+    } else {
+        process::Command::new("sh").args(["-c", command]).status()
+    };
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("build command '{}' failed: {}", command, status)),
+        Err(e) => Err(format!("could not run build command '{}': {}", command, e)),
+    }
+}
+
+fn create_recorder(path: &Path, tone: Tone) -> Result<Recorder, String> {
+    let writer = wav::create_file(path, SAMPLE_RATE as u32)
+        .map_err(|e| format!("could not create '{}': {}", path.display(), e))?;
+    Ok(AudioRecorder::new(writer, tone, SAMPLE_RATE as u32, FRAME_RATE))
+}
+
+fn record_frame(recorder: &mut Option<Recorder>, chip8: &Chip8) -> Result<(), String> {
+    match recorder {
+        Some(recorder) => recorder.record_frame(chip8.sound_events(), chip8.samples())
+            .map_err(|e| format!("could not record audio: {}", e)),
+        None => Ok(()),
+    }
+}
+
+fn finish_recording(recorder: Option<Recorder>) -> Result<(), String> {
+    match recorder {
+        Some(mut recorder) => recorder.finish()
+            .map_err(|e| format!("could not record audio: {}", e)),
+        None => Ok(()),
+    }
+}
+
+/// Run as fast as possible without a window, then print what's on the display
+fn run_headless(chip8: &mut Chip8, cycles_per_frame: usize, frames: u64, input: &mut Input, recorder: &mut Option<Recorder>) -> Result<(), String> {
+    for _ in 0..frames {
+        input.next_frame(&[0; 16], &[0; 16], &mut chip8.keypad, &mut chip8.second_keypad).map_err(|e| format!("could not record movie: {}", e))?;
+        chip8.run_frame(cycles_per_frame);
+        record_frame(recorder, chip8)?;
+    }
+
+    chip8.video.rows().for_each(|row| {
+        // Anything but black is lit, coloured displays are opaque all over
+        let line: String = row.iter().map(|p| if *p >> 8 != 0 { '█' } else { ' ' }).collect();
+        println!("{}", line.trim_end());
+    });
+
+    Ok(())
 }
@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::rom_hash;
+use crate::romdb::RomDatabase;
+
+/// File extensions treated as ROMs when listing a directory, including those of the CHIP-8X,
+/// CHIP-8E, HIRES and MegaChip variants
+const ROM_EXTENSIONS: [&str; 8] = ["ch8", "c8", "chip8", "rom", "c8x", "c8e", "c8h", "mc8"];
+
+/// Number of ROMs shown at once, and moved by a page up or down
+pub const PAGE_SIZE: usize = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    /// The title from the ROM database, or the file name
+    pub title: String,
+}
+
+/// List the ROMs in a directory, sorted by title.
+pub fn scan(dir: &Path, database: &RomDatabase) -> Result<Vec<RomEntry>, String> {
+    let files = fs::read_dir(dir).map_err(|e| format!("could not read ROM directory '{}': {}", dir.display(), e))?;
+
+    let mut entries: Vec<RomEntry> = files
+        .filter_map(|file| file.ok().map(|file| file.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            ROM_EXTENSIONS.iter().any(|ext| ext.eq_ignore_ascii_case(extension))
+        })
+        .map(|path| {
+            let title = fs::read(&path).ok()
+                .and_then(|rom| database.lookup(&rom_hash(&rom)).title)
+                .unwrap_or_else(|| path.file_name().unwrap_or_default().to_string_lossy().into_owned());
+            RomEntry { path, title }
+        })
+        .collect();
+
+    entries.sort_by_key(|entry| entry.title.to_lowercase());
+    Ok(entries)
+}
+
+/// A scrolling list of ROMs to pick from.
+pub struct Menu {
+    entries: Vec<RomEntry>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl Menu {
+    pub fn new(entries: Vec<RomEntry>) -> Self {
+        Menu { entries, selected: 0, scroll: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn selected(&self) -> Option<&RomEntry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn up(&mut self) {
+        self.select(self.selected.saturating_sub(1));
+    }
+
+    pub fn down(&mut self) {
+        self.select(self.selected + 1);
+    }
+
+    pub fn page_up(&mut self) {
+        self.select(self.selected.saturating_sub(PAGE_SIZE));
+    }
+
+    pub fn page_down(&mut self) {
+        self.select(self.selected + PAGE_SIZE);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.entries.len().saturating_sub(1));
+
+        // Scroll just far enough to keep the selection on screen
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + PAGE_SIZE {
+            self.scroll = self.selected + 1 - PAGE_SIZE;
+        }
+    }
+
+    /// The titles on screen, and which of them is selected
+    pub fn page(&self) -> (Vec<String>, usize) {
+        let titles = self.entries.iter()
+            .skip(self.scroll)
+            .take(PAGE_SIZE)
+            .map(|entry| entry.title.clone())
+            .collect();
+        (titles, self.selected - self.scroll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu(count: usize) -> Menu {
+        Menu::new((0..count)
+            .map(|i| RomEntry { path: PathBuf::from(format!("{}.ch8", i)), title: format!("ROM {}", i) })
+            .collect())
+    }
+
+    #[test]
+    fn it_lists_roms_in_a_directory() {
+        let dir = std::env::temp_dir().join(format!("chip8-rs-menu-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.ch8"), [0x12, 0x00]).unwrap();
+        fs::write(dir.join("A.C8"), [0x12, 0x02]).unwrap();
+        fs::write(dir.join("c.mc8"), [0x00, 0x11]).unwrap();
+        fs::write(dir.join("readme.txt"), "not a ROM").unwrap();
+
+        let entries = scan(&dir, &RomDatabase::builtin()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let titles: Vec<&str> = entries.iter().map(|entry| entry.title.as_str()).collect();
+        assert_eq!(titles, vec!["A.C8", "b.ch8", "c.mc8"]);
+        assert_eq!(entries[1].path, dir.join("b.ch8"));
+    }
+
+    #[test]
+    fn it_moves_the_selection() {
+        let mut menu = menu(3);
+        assert_eq!(menu.selected().unwrap().title, "ROM 0");
+
+        menu.up();
+        assert_eq!(menu.selected().unwrap().title, "ROM 0");
+        menu.down();
+        menu.down();
+        menu.down();
+        assert_eq!(menu.selected().unwrap().title, "ROM 2");
+
+        assert_eq!(Menu::new(Vec::new()).selected(), None);
+    }
+
+    #[test]
+    fn it_scrolls_to_keep_the_selection_visible() {
+        let mut menu = menu(50);
+        let (titles, selected) = menu.page();
+        assert_eq!((titles.len(), titles[0].as_str(), selected), (20, "ROM 0", 0));
+
+        menu.page_down();
+        menu.down();
+        let (titles, selected) = menu.page();
+        assert_eq!((titles[0].as_str(), selected), ("ROM 2", 19));
+
+        menu.page_down();
+        menu.page_down();
+        let (titles, selected) = menu.page();
+        assert_eq!((titles.len(), titles[0].as_str(), selected), (20, "ROM 30", 19));
+
+        menu.up();
+        menu.page_up();
+        let (titles, selected) = menu.page();
+        assert_eq!((titles[0].as_str(), selected), ("ROM 28", 0));
+    }
+}
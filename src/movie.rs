@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::machine::Machine;
+use crate::quirks::Quirks;
+use crate::timing::Timing;
+
+const MAGIC: &[u8; 8] = b"CHIP8MOV";
+/// Bumped whenever a released movie format changes
+const VERSION: u8 = 1;
+
+/// The keypad as a bit per key, key 0 in the lowest bit
+pub fn pack_keys(keypad: &[u8]) -> u16 {
+    keypad.iter()
+        .enumerate()
+        .filter(|(_, pressed)| **pressed != 0)
+        .fold(0, |keys, (key, _)| keys | 1 << key)
+}
+
+pub fn unpack_keys(keys: u16, keypad: &mut [u8]) {
+    for (key, pressed) in keypad.iter_mut().enumerate() {
+        *pressed = (keys >> key & 1) as u8;
+    }
+}
+
+fn invalid_data<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Everything besides the input that's needed to replay a session exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieHeader {
+    /// Seed for the random number generator
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub machine: Machine,
+    /// SHA-1 of the ROM the movie was recorded with
+    pub rom_hash: String,
+    /// SHA-1 of the interpreter the VIP machine ran, if that's what the movie was recorded on
+    pub interpreter_hash: Option<String>,
+    pub cycles_per_frame: usize,
+}
+
+impl MovieHeader {
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let quirks = self.quirks.to_string();
+        let timing = self.timing.to_string();
+        let machine = self.machine.to_string();
+        let interpreter_hash = self.interpreter_hash.as_deref().unwrap_or_default();
+
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&(self.cycles_per_frame as u32).to_le_bytes())?;
+        out.write_all(&(self.rom_hash.len() as u8).to_le_bytes())?;
+        out.write_all(self.rom_hash.as_bytes())?;
+        out.write_all(&(quirks.len() as u8).to_le_bytes())?;
+        out.write_all(quirks.as_bytes())?;
+        out.write_all(&(timing.len() as u8).to_le_bytes())?;
+        out.write_all(timing.as_bytes())?;
+        out.write_all(&(machine.len() as u8).to_le_bytes())?;
+        out.write_all(machine.as_bytes())?;
+        out.write_all(&(interpreter_hash.len() as u8).to_le_bytes())?;
+        out.write_all(interpreter_hash.as_bytes())
+    }
+
+    fn read<R: Read>(input: &mut R) -> io::Result<MovieHeader> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a movie file"));
+        }
+
+        let mut version = [0; 1];
+        input.read_exact(&mut version)?;
+        if version[0] != VERSION {
+            return Err(invalid_data(format!("unsupported movie version {}, this version of chip8-rs plays version {}", version[0], VERSION)));
+        }
+
+        let mut seed = [0; 8];
+        input.read_exact(&mut seed)?;
+        let mut cycles_per_frame = [0; 4];
+        input.read_exact(&mut cycles_per_frame)?;
+        let rom_hash = read_string(input)?;
+        let quirks = read_string(input)?.parse().map_err(invalid_data)?;
+        let timing = read_string(input)?.parse().map_err(invalid_data)?;
+        let machine = read_string(input)?.parse().map_err(invalid_data)?;
+        let interpreter_hash = Some(read_string(input)?).filter(|hash| !hash.is_empty());
+
+        Ok(MovieHeader {
+            seed: u64::from_le_bytes(seed),
+            quirks,
+            timing,
+            machine,
+            rom_hash,
+            interpreter_hash,
+            cycles_per_frame: u32::from_le_bytes(cycles_per_frame) as usize,
+        })
+    }
+}
+
+/// A string prefixed by its length in bytes
+fn read_string<R: Read>(input: &mut R) -> io::Result<String> {
+    let mut length = [0; 1];
+    input.read_exact(&mut length)?;
+    let mut bytes = vec![0; length[0] as usize];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+}
+
+/// A recorded session: the header followed by the keypad state for every frame, with the second
+/// keypad in the upper 16 bits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<u32>,
+}
+
+impl Movie {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Movie> {
+        Movie::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: Read>(mut input: R) -> io::Result<Movie> {
+        let header = MovieHeader::read(&mut input)?;
+
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        if bytes.len() % 4 != 0 {
+            return Err(invalid_data("movie ends part way through a frame"));
+        }
+        let frames = bytes.chunks(4).map(|frame| u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]])).collect();
+
+        Ok(Movie { header, frames })
+    }
+}
+
+/// Writes a movie a frame at a time as it's recorded.
+pub struct MovieWriter<W: Write> {
+    out: W,
+}
+
+impl MovieWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, header: &MovieHeader) -> io::Result<Self> {
+        MovieWriter::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> MovieWriter<W> {
+    pub fn new(mut out: W, header: &MovieHeader) -> io::Result<Self> {
+        header.write(&mut out)?;
+        Ok(MovieWriter { out })
+    }
+
+    pub fn record_frame(&mut self, keys: u32) -> io::Result<()> {
+        self.out.write_all(&keys.to_le_bytes())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Where the keypad state for each frame comes from: the player, the player while they're being
+/// recorded, or a movie being played back. Going through here rather than setting the keypad
+/// straight from the keyboard is what makes sessions recordable.
+pub enum Input {
+    Live,
+    Record(MovieWriter<BufWriter<File>>),
+    Playback { frames: Vec<u32>, position: usize },
+}
+
+impl Input {
+    /// Set both keypads for the next frame from the keys the player is holding, or from the movie
+    pub fn next_frame(&mut self, live: &[u8], live2: &[u8], keypad: &mut [u8], keypad2: &mut [u8]) -> io::Result<()> {
+        match self {
+            Input::Live => {
+                keypad.copy_from_slice(live);
+                keypad2.copy_from_slice(live2);
+            }
+            Input::Record(writer) => {
+                keypad.copy_from_slice(live);
+                keypad2.copy_from_slice(live2);
+                writer.record_frame(pack_keys(live) as u32 | (pack_keys(live2) as u32) << 16)?;
+            }
+            Input::Playback { frames, position } => match frames.get(*position) {
+                Some(keys) => {
+                    unpack_keys(*keys as u16, keypad);
+                    unpack_keys((*keys >> 16) as u16, keypad2);
+                    *position += 1;
+                }
+                None => {
+                    keypad.copy_from_slice(live);
+                    keypad2.copy_from_slice(live2);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Whether a movie is being recorded or played back
+    pub fn is_movie(&self) -> bool {
+        !matches!(self, Input::Live)
+    }
+
+    /// Whether a movie being played back has run out of frames
+    pub fn finished(&self) -> bool {
+        matches!(self, Input::Playback { frames, position } if *position >= frames.len())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Input::Record(writer) => writer.finish(),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> MovieHeader {
+        MovieHeader {
+            seed: 42,
+            quirks: "vip,-memory".parse().unwrap(),
+            timing: Timing::Vip,
+            machine: Machine::Vip,
+            rom_hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            interpreter_hash: Some("89abcdef0123456789abcdef0123456789abcdef".to_string()),
+            cycles_per_frame: 15,
+        }
+    }
+
+    #[test]
+    fn it_packs_keys() {
+        let mut keypad = [0; 16];
+        keypad[0] = 1;
+        keypad[0xF] = 1;
+        assert_eq!(pack_keys(&keypad), 0x8001);
+
+        let mut unpacked = [1; 16];
+        unpack_keys(0x8001, &mut unpacked);
+        assert_eq!(unpacked, keypad);
+    }
+
+    #[test]
+    fn it_round_trips_movies() {
+        let mut writer = MovieWriter::new(Vec::new(), &header()).unwrap();
+        writer.record_frame(0x0000).unwrap();
+        writer.record_frame(0x0010).unwrap();
+        writer.record_frame(0xFFFF).unwrap();
+        writer.record_frame(0x8000_0001).unwrap();
+        let bytes = writer.into_inner();
+
+        let movie = Movie::read(bytes.as_slice()).unwrap();
+        assert_eq!(movie, Movie { header: header(), frames: vec![0x0000, 0x0010, 0xFFFF, 0x8000_0001] });
+
+        assert!(Movie::read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Movie::read(&b"CHIP8ROM"[..]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        let error = Movie::read(newer.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported movie version 2, this version of chip8-rs plays version 1");
+
+        // Without the VIP machine there's no interpreter
+        let header = MovieHeader { machine: Machine::Chip8, interpreter_hash: None, ..header() };
+        let bytes = MovieWriter::new(Vec::new(), &header).unwrap().into_inner();
+        assert_eq!(Movie::read(bytes.as_slice()).unwrap().header, header);
+    }
+
+    #[test]
+    fn it_plays_movies_back() {
+        let mut input = Input::Playback { frames: vec![0x0004_0002], position: 0 };
+        let mut live = [0; 16];
+        live[5] = 1;
+        let mut keypad = [0; 16];
+        let mut keypad2 = [0; 16];
+
+        // The movie's keys are used rather than the player's until it runs out
+        input.next_frame(&live, &live, &mut keypad, &mut keypad2).unwrap();
+        assert_eq!(pack_keys(&keypad), 0x0002);
+        assert_eq!(pack_keys(&keypad2), 0x0004);
+        assert!(input.finished());
+
+        input.next_frame(&live, &[0; 16], &mut keypad, &mut keypad2).unwrap();
+        assert_eq!(keypad, live);
+        assert_eq!(keypad2, [0; 16]);
+        assert!(!Input::Live.finished());
+    }
+}
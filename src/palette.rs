@@ -0,0 +1,95 @@
+use std::str::FromStr;
+
+/// Colours used to draw lit and unlit pixels, stored as RGBA8888.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub foreground: u32,
+    pub background: u32,
+}
+
+impl Palette {
+    pub const WHITE: Palette = Palette { foreground: 0xFFFFFFFF, background: 0x000000FF };
+    pub const AMBER: Palette = Palette { foreground: 0xFFB000FF, background: 0x1A0F00FF };
+    pub const GREEN: Palette = Palette { foreground: 0x33FF33FF, background: 0x051A05FF };
+    pub const LCD: Palette = Palette { foreground: 0x0F380FFF, background: 0x9BBC0FFF };
+
+    const PRESETS: [(&str, Palette); 4] = [
+        ("white", Palette::WHITE),
+        ("amber", Palette::AMBER),
+        ("green", Palette::GREEN),
+        ("lcd", Palette::LCD),
+    ];
+
+    /// Colour a pixel by its brightness, mixing between the background and foreground colours.
+    pub fn shade(&self, pixel: u32) -> u32 {
+        let level = pixel >> 24;
+        match level {
+            0 => self.background,
+            0xFF => self.foreground,
+            _ => {
+                let mix = |shift: u32| {
+                    let fg = (self.foreground >> shift) & 0xFF;
+                    let bg = (self.background >> shift) & 0xFF;
+                    ((fg * level + bg * (0xFF - level)) / 0xFF) << shift
+                };
+                mix(24) | mix(16) | mix(8) | 0xFF
+            }
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::WHITE
+    }
+}
+
+pub(crate) fn parse_colour(s: &str) -> Result<u32, String> {
+    let hex = s.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok((rgb << 8) | 0xFF),
+        _ => Err(format!("invalid colour '{}', expected RRGGBB", s)),
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parse a preset name or a `foreground,background` pair of RRGGBB colours.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, palette)) = Palette::PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(*palette);
+        }
+
+        let colours: Vec<&str> = s.split(',').map(str::trim).collect();
+        match colours.as_slice() {
+            [fg, bg] => Ok(Palette { foreground: parse_colour(fg)?, background: parse_colour(bg)? }),
+            _ => Err(format!(
+                "unknown palette '{}', expected RRGGBB,RRGGBB or one of {}",
+                s,
+                Palette::PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_palettes() {
+        assert_eq!("amber".parse(), Ok(Palette::AMBER));
+        assert_eq!("#FF0000,000080".parse(), Ok(Palette { foreground: 0xFF0000FF, background: 0x000080FF }));
+        assert!("FF0000".parse::<Palette>().is_err());
+        assert!("red,blue".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn it_shades_pixels() {
+        let palette = Palette { foreground: 0xFF0000FF, background: 0x0000FFFF };
+        assert_eq!(palette.shade(0x00000000), 0x0000FFFF);
+        assert_eq!(palette.shade(0xFFFFFFFF), 0xFF0000FF);
+        assert_eq!(palette.shade(0x808080FF), 0x80007FFF);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+/// How successive frames are combined before they are presented.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    /// Show each frame exactly as the interpreter produced it.
+    Direct,
+    /// Lit pixels fade out by the given factor (0.0 - 1.0) every frame, like a slow phosphor.
+    Decay(f32),
+    /// Show the OR of the last N frames.
+    Blend(usize),
+}
+
+impl Default for RenderMode {
+    fn default() -> Self {
+        RenderMode::Direct
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    /// Parse `direct`, `decay:<factor>` or `blend:<frames>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        match (name, value) {
+            ("direct", None) => Ok(RenderMode::Direct),
+            ("decay", Some(v)) => match v.parse::<f32>() {
+                Ok(decay) if (0.0..=1.0).contains(&decay) => Ok(RenderMode::Decay(decay)),
+                _ => Err(format!("decay must be between 0.0 and 1.0, got '{}'", v)),
+            },
+            ("blend", Some(v)) => match v.parse::<usize>() {
+                Ok(frames) if frames > 0 => Ok(RenderMode::Blend(frames)),
+                _ => Err(format!("blend must be a positive number of frames, got '{}'", v)),
+            },
+            _ => Err(format!("unknown render mode '{}', expected direct, decay:<factor> or blend:<frames>", s)),
+        }
+    }
+}
+
+/// Reduces sprite flicker by combining the current frame with the ones before it.
+pub struct Phosphor {
+    mode: RenderMode,
+    intensity: Vec<f32>,
+    history: VecDeque<Vec<u32>>,
+    output: Vec<u32>,
+}
+
+impl Phosphor {
+    pub fn new(mode: RenderMode, size: usize) -> Self {
+        Phosphor {
+            mode,
+            intensity: vec![0.0; size],
+            history: VecDeque::new(),
+            output: vec![0; size],
+        }
+    }
+
+    pub fn mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+        self.intensity.fill(0.0);
+        self.history.clear();
+    }
+
+    /// Combine a frame with the persisted state and return the pixels to present.
+    pub fn apply(&mut self, frame: &[u32]) -> &[u32] {
+        if self.output.len() != frame.len() {
+            self.intensity = vec![0.0; frame.len()];
+            self.history.clear();
+            self.output = vec![0; frame.len()];
+        }
+
+        match self.mode {
+            RenderMode::Direct => self.output.copy_from_slice(frame),
+            RenderMode::Decay(decay) => {
+                let decay = decay.clamp(0.0, 1.0);
+                for ((level, out), pixel) in self.intensity.iter_mut().zip(self.output.iter_mut()).zip(frame) {
+                    // A lit pixel is at full brightness, an unlit one fades from where it was
+                    *level = if *pixel != 0 { 1.0 } else { *level * decay };
+                    *out = grey(*level);
+                }
+            }
+            RenderMode::Blend(frames) => {
+                self.history.push_back(frame.to_vec());
+                while self.history.len() > frames.max(1) {
+                    self.history.pop_front();
+                }
+
+                self.output.fill(0);
+                for past in self.history.iter() {
+                    self.output.iter_mut().zip(past).for_each(|(out, p)| *out |= *p);
+                }
+            }
+        }
+
+        &self.output
+    }
+}
+
+/// Convert an intensity (0.0 - 1.0) to an RGBA8888 grey level.
+fn grey(level: f32) -> u32 {
+    if level < 1.0 / 255.0 {
+        return 0;
+    }
+
+    let v = (level * 255.0).round() as u32;
+    (v << 24) | (v << 16) | (v << 8) | 0xFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_render_modes() {
+        assert_eq!("direct".parse(), Ok(RenderMode::Direct));
+        assert_eq!("decay:0.75".parse(), Ok(RenderMode::Decay(0.75)));
+        assert_eq!("blend:3".parse(), Ok(RenderMode::Blend(3)));
+        assert!("decay:2".parse::<RenderMode>().is_err());
+        assert!("blend:0".parse::<RenderMode>().is_err());
+        assert!("glow".parse::<RenderMode>().is_err());
+    }
+
+    #[test]
+    fn it_passes_frames_through_in_direct_mode() {
+        let mut phosphor = Phosphor::new(RenderMode::Direct, 4);
+        let frame = [0, 0xFFFFFFFF, 0, 0xFFFFFFFF];
+        assert_eq!(phosphor.apply(&frame), &frame);
+    }
+
+    #[test]
+    fn it_decays_unlit_pixels() {
+        let mut phosphor = Phosphor::new(RenderMode::Decay(0.5), 2);
+
+        // Fully lit on the first frame
+        assert_eq!(phosphor.apply(&[0xFFFFFFFF, 0]), &[0xFFFFFFFF, 0]);
+
+        // Half brightness once the pixel is turned off
+        assert_eq!(phosphor.apply(&[0, 0]), &[0x808080FF, 0]);
+
+        // Lit pixels go straight back to full brightness
+        assert_eq!(phosphor.apply(&[0xFFFFFFFF, 0]), &[0xFFFFFFFF, 0]);
+
+        // Faded pixels eventually go dark
+        (0..10).for_each(|_| { phosphor.apply(&[0, 0]); });
+        assert_eq!(phosphor.apply(&[0, 0]), &[0, 0]);
+    }
+
+    #[test]
+    fn it_blends_the_last_n_frames() {
+        let mut phosphor = Phosphor::new(RenderMode::Blend(2), 3);
+
+        assert_eq!(phosphor.apply(&[0xFFFFFFFF, 0, 0]), &[0xFFFFFFFF, 0, 0]);
+        assert_eq!(phosphor.apply(&[0, 0xFFFFFFFF, 0]), &[0xFFFFFFFF, 0xFFFFFFFF, 0]);
+
+        // The first frame has dropped out of the window
+        assert_eq!(phosphor.apply(&[0, 0, 0xFFFFFFFF]), &[0, 0xFFFFFFFF, 0xFFFFFFFF]);
+    }
+}
@@ -1,138 +1,712 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::sync::mpsc::{self, Receiver, Sender};
+
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
 use sdl2::event::Event;
+use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
 use sdl2::render::{Texture, TextureCreator, WindowCanvas};
-use sdl2::Sdl;
-use sdl2::video::WindowContext;
-use crate::SoundState;
-
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+use sdl2::{GameControllerSubsystem, Sdl};
+use sdl2::sys::SDL_RendererFlags;
+use sdl2::video::{FullscreenType, WindowContext};
+use crate::audio::{AudioBackend, AudioSink, NullAudio, SAMPLE_RATE, SampleQueue, SoundEvent, SoundScheduler, Synth, Tone};
+use crate::display::Display;
+use crate::hud::{self, Hud};
+use crate::machine::ColourOverlay;
+use crate::palette::Palette;
+use crate::phosphor::{Phosphor, RenderMode};
+use crate::timing::FRAME_RATE;
+use crate::wav::{self, AudioRecorder};
+use crate::window::{self, WindowState};
+
+/// Plays the buzzer as the emulator switches it on and off, and any digitised sound over it. The
+/// device keeps running while the buzzer is off so the synth can fade the tone out rather than
+/// cutting it off with a click.
+struct Beeper {
+    synth: Synth,
+    scheduler: SoundScheduler,
+    events: Receiver<SoundEvent>,
+    queue: SampleQueue,
+    samples: Receiver<Vec<f32>>,
+    /// Buffers of samples that have been queued, sent back to be filled again
+    spent: Sender<Vec<f32>>,
+    muted: bool,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Beeper {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
+        for event in self.events.try_iter() {
+            self.scheduler.push(event);
+        }
+        // Buffers go back to be reused rather than being freed here, where it could hold up the audio
+        for samples in self.samples.try_iter() {
+            self.queue.extend(&samples);
+            let _ = self.spent.send(samples);
+        }
+
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            let gate = self.scheduler.next_gate() && !self.muted;
+            let sample = self.queue.next_sample();
+            *x = self.synth.next_sample(gate) + if self.muted { 0.0 } else { sample };
+        }
+    }
+}
+
+/// The default SDL audio device
+struct SdlAudio {
+    device: AudioDevice<Beeper>,
+    events: Sender<SoundEvent>,
+    samples: Sender<Vec<f32>>,
+    spent: Receiver<Vec<f32>>,
+}
+
+impl SdlAudio {
+    fn open(context: &Sdl) -> Result<SdlAudio, String> {
+        let audio_subsystem = context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),  // mono
+            samples: None,       // default sample size
+        };
+
+        let (sender, events) = mpsc::channel();
+        let (sample_sender, samples) = mpsc::channel();
+        let (spent, spent_receiver) = mpsc::channel();
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            // Schedule sound a frame behind the emulator, plus a buffer's worth for the device itself
+            let latency = spec.samples as usize + spec.freq as usize / FRAME_RATE as usize;
+            Beeper {
+                synth: Synth::new(Tone::default(), spec.freq),
+                scheduler: SoundScheduler::new(spec.freq, FRAME_RATE, latency),
+                events,
+                queue: SampleQueue::new(spec.freq, FRAME_RATE),
+                samples,
+                spent,
+                muted: false,
+            }
+        })?;
+        device.resume();
+
+        Ok(SdlAudio { device, events: sender, samples: sample_sender, spent: spent_receiver })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_tone(&mut self, tone: Tone) {
+        self.device.lock().synth.set_tone(tone);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.device.lock().muted = muted;
+    }
+
+    fn play(&mut self, events: &[SoundEvent], samples: &[f32]) -> io::Result<()> {
+        // The callback only goes away along with the device
+        for event in events {
+            self.events.send(*event).unwrap();
+        }
+        if !samples.is_empty() {
+            let mut buffer = self.spent.try_recv().unwrap_or_default();
+            buffer.clear();
+            buffer.extend_from_slice(samples);
+            self.samples.send(buffer).unwrap();
+        }
+        Ok(())
+    }
+}
+
+/// Open an audio backend. If the audio device can't be opened the emulator carries on without sound.
+pub fn open_audio(context: &Sdl, backend: &AudioBackend) -> Result<Box<dyn AudioSink>, String> {
+    match backend {
+        AudioBackend::Sdl => match SdlAudio::open(context) {
+            Ok(audio) => Ok(Box::new(audio)),
+            Err(e) => {
+                eprintln!("warning: could not open the audio device, continuing without sound: {}", e);
+                Ok(Box::new(NullAudio))
+            }
+        },
+        AudioBackend::Null => Ok(Box::new(NullAudio)),
+        AudioBackend::File(path) => {
+            let writer = wav::create_file(path, SAMPLE_RATE as u32)
+                .map_err(|e| format!("could not create '{}': {}", path.display(), e))?;
+            Ok(Box::new(AudioRecorder::new(writer, Tone::default(), SAMPLE_RATE as u32, FRAME_RATE)))
         }
     }
 }
 
+fn colour(rgba: u32) -> Color {
+    Color::RGBA((rgba >> 24) as u8, (rgba >> 16) as u8, (rgba >> 8) as u8, rgba as u8)
+}
+
+/// Frontend hotkeys, for anything that isn't the CHIP-8 keypad.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    TogglePause,
+    Reset,
+    SpeedUp,
+    SpeedDown,
+    /// A file was dropped on the window
+    LoadRom(PathBuf),
+    /// Open or close the ROM menu
+    ToggleMenu,
+    Menu(MenuAction),
+    /// Run faster than real time for as long as the key is held
+    FastForward(bool),
+    /// Step back through earlier states for as long as the key is held
+    Rewind(bool),
+}
+
+/// Moving around the ROM menu, while it's open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuAction {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Select,
+    Close,
+}
+
+/// Emulator state shown over the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indicator {
+    None,
+    Paused,
+    FastForward,
+    Rewind,
+}
+
+const DEFAULT_KEYMAP: [Keycode; 16] = [
+    Keycode::X, Keycode::Num1, Keycode::Num2, Keycode::Num3,
+    Keycode::Q, Keycode::W, Keycode::E, Keycode::A,
+    Keycode::S, Keycode::D, Keycode::Z, Keycode::C,
+    Keycode::Num4, Keycode::R, Keycode::F, Keycode::V,
+];
+
+type Keymap = [Option<Keycode>; 16];
+
+/// The keymaps for both keypads: the default layout with CHIP-8 keys (0x0-0xF) bound to other
+/// keyboard keys, and the second keypad's bindings, it has no default layout. A keyboard key is
+/// only ever bound to one CHIP-8 key, one whose default it took is left unbound.
+fn keymaps(bindings: &[(usize, Keycode)], second_bindings: &[(usize, Keycode)]) -> (Keymap, Keymap) {
+    let mut keymap = DEFAULT_KEYMAP.map(Some);
+    let mut second = [None; 16];
+    for (key, keycode) in bindings {
+        keymap.iter_mut().filter(|k| **k == Some(*keycode)).for_each(|k| *k = None);
+        keymap[*key] = Some(*keycode);
+    }
+    for (key, keycode) in second_bindings {
+        keymap.iter_mut().chain(second.iter_mut()).filter(|k| **k == Some(*keycode)).for_each(|k| *k = None);
+        second[*key] = Some(*keycode);
+    }
+    (keymap, second)
+}
+
+/// Look up keyboard keys by their SDL names
+fn keycodes(bindings: &[(usize, &str)]) -> Result<Vec<(usize, Keycode)>, String> {
+    bindings.iter()
+        .map(|(key, name)| match Keycode::from_name(name) {
+            Some(keycode) => Ok((*key, keycode)),
+            None => Err(format!("unknown keyboard key '{}' bound to CHIP-8 key {:X}", name, key)),
+        })
+        .collect()
+}
+
 pub struct Platform<'tex> {
-    context: Sdl,
+    _context: Sdl,
+    event_pump: EventPump,
     canvas: WindowCanvas,
+    texture_creator: &'tex TextureCreator<WindowContext>,
     texture: Texture<'tex>,
-    audio_device: AudioDevice<SquareWave>,
+    audio: Box<dyn AudioSink>,
+    phosphor: Phosphor,
+    palette: Palette,
+    pixels: Vec<u32>,
+    keymap: Keymap,
+    /// The second keypad, CHIP-8X has one
+    second_keymap: Keymap,
+    texture_size: (u32, u32),
+    integer_scaling: bool,
+    indicator: Indicator,
+    hud: Hud,
+    menu_open: bool,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    vsync: bool,
 }
 
 impl<'tex> Platform<'tex> {
-    pub fn create_window_canvas_texture_creator(title: &str, window_width: u32, window_height: u32) -> (Sdl, WindowCanvas, TextureCreator<WindowContext>) {
+    pub fn create_window_canvas_texture_creator(title: &str, window_width: u32, window_height: u32, position: Option<(i32, i32)>, vsync: bool, fullscreen: bool) -> (Sdl, WindowCanvas, TextureCreator<WindowContext>) {
         let context = sdl2::init().unwrap();
         let video_subsystem = context.video().unwrap();
 
-        let window = video_subsystem.window(title, window_width, window_height)
-            .position_centered()
-            .build()
-            .unwrap();
+        let mut window_builder = video_subsystem.window(title, window_width, window_height);
+        window_builder.resizable();
+        match position {
+            Some((x, y)) => window_builder.position(x, y),
+            None => window_builder.position_centered(),
+        };
+        if fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+        let window = window_builder.build().unwrap();
+
+        // Only lock presentation to the display when it refreshes at the emulated frame rate,
+        // otherwise the game speed would follow the monitor
+        let refresh_rate = window.display_index()
+            .and_then(|i| video_subsystem.current_display_mode(i))
+            .map(|mode| mode.refresh_rate)
+            .unwrap_or(0);
 
-        let canvas = window.into_canvas().build().unwrap();
+        let canvas = if vsync && refresh_rate == FRAME_RATE as i32 {
+            window.into_canvas().present_vsync().build().unwrap()
+        } else {
+            window.into_canvas().build().unwrap()
+        };
         let texture_creator = canvas.texture_creator();
 
         (context, canvas, texture_creator)
     }
 
-    pub fn new(context: Sdl, canvas: WindowCanvas, texture_creator: &'tex TextureCreator<WindowContext>, texture_width: u32, texture_height: u32) -> Self {
+    pub fn new(context: Sdl, canvas: WindowCanvas, texture_creator: &'tex TextureCreator<WindowContext>, texture_width: u32, texture_height: u32, audio: Box<dyn AudioSink>) -> Self {
         let texture = texture_creator.create_texture_streaming(
             PixelFormatEnum::RGBA8888, texture_width, texture_height).unwrap();
 
-        let audio_subsystem = context.audio().unwrap();
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),  // mono
-            samples: None,       // default sample size
+        let phosphor = Phosphor::new(RenderMode::Direct, (texture_width * texture_height) as usize);
+        let vsync = canvas.info().flags & SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
+        let event_pump = context.event_pump().unwrap();
+
+        // Controllers are only used for the menu, so carry on without them if they're unavailable
+        let controller_subsystem = context.game_controller().ok();
+
+        Platform {
+            _context: context,
+            event_pump,
+            canvas,
+            texture_creator,
+            texture,
+            audio,
+            phosphor,
+            palette: Palette::default(),
+            pixels: vec![0; (texture_width * texture_height) as usize],
+            keymap: DEFAULT_KEYMAP.map(Some),
+            second_keymap: [None; 16],
+            texture_size: (texture_width, texture_height),
+            integer_scaling: false,
+            indicator: Indicator::None,
+            hud: Hud::new(),
+            menu_open: false,
+            controller_subsystem,
+            controllers: Vec::new(),
+            vsync,
+        }
+    }
+
+    /// Returns true if presenting a frame blocks until the next 60 Hz refresh
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.phosphor.set_mode(mode);
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Only scale the display by whole numbers, so every CHIP-8 pixel is the same size
+    pub fn set_integer_scaling(&mut self, integer_scaling: bool) {
+        self.integer_scaling = integer_scaling;
+    }
+
+    pub fn set_indicator(&mut self, indicator: Indicator) {
+        self.indicator = indicator;
+    }
+
+    pub fn hud(&mut self) -> &mut Hud {
+        &mut self.hud
+    }
+
+    /// While the menu is open the keyboard and controllers navigate it rather than the CHIP-8 keypad
+    pub fn set_menu_open(&mut self, open: bool) {
+        self.menu_open = open;
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        // Only fails for titles containing a nul
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
         };
+        // Not every platform can go fullscreen, staying windowed is fine
+        let _ = window.set_fullscreen(fullscreen);
+    }
 
-        let audio_device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25,
-            }
-        }).unwrap();
+    /// The window's position and size, unless it's fullscreen
+    pub fn window_state(&self) -> Option<WindowState> {
+        let window = self.canvas.window();
+        if window.fullscreen_state() != FullscreenType::Off {
+            return None;
+        }
 
-        Platform { context, canvas, texture, audio_device }
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        Some(WindowState { x, y, width, height })
     }
 
-    pub fn update(&mut self, buffer: &[u32], pitch: usize, sound_state: &SoundState) {
-        self.texture.update(None, unsafe { &buffer.align_to::<u8>().1 }, pitch).unwrap();
+    pub fn set_muted(&mut self, muted: bool) {
+        self.audio.set_muted(muted);
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.audio.set_tone(tone);
+    }
+
+    /// Draw a frame of the display, in the palette's colours or the CHIP-8X colour overlay's.
+    /// The texture follows the display if it changes size, e.g. for a ROM with a bigger one.
+    pub fn update(&mut self, display: &Display, overlay: Option<&ColourOverlay>) {
+        let (display_width, display_height) = (display.width() as u32, display.height() as u32);
+        if self.texture_size != (display_width, display_height) {
+            self.texture = self.texture_creator.create_texture_streaming(
+                PixelFormatEnum::RGBA8888, display_width, display_height).unwrap();
+            self.texture_size = (display_width, display_height);
+            self.pixels = vec![0; display.len()];
+            self.phosphor.set_mode(self.phosphor.mode());
+        }
+
+        let width = self.texture_size.0 as usize;
+        if display.is_coloured() {
+            self.pixels.copy_from_slice(display);
+        } else {
+            let frame = self.phosphor.apply(display);
+            let palette = self.palette;
+            for (i, (out, p)) in self.pixels.iter_mut().zip(frame).enumerate() {
+                *out = match overlay {
+                    Some(overlay) => overlay.palette(i % width, i / width).shade(*p),
+                    None => palette.shade(*p),
+                };
+            }
+        }
+
+        let pitch = std::mem::size_of::<u32>() * width;
+        self.texture.update(None, unsafe { &self.pixels.align_to::<u8>().1 }, pitch).unwrap();
+        let (window_width, window_height) = self.canvas.output_size().unwrap();
+        let (width, height) = self.texture_size;
+        let viewport = window::viewport(window_width, window_height, width, height, self.integer_scaling);
+
         self.canvas.clear();
-        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.copy(&self.texture, None, viewport).unwrap();
+        self.draw_indicator(viewport);
+
+        let now = Instant::now();
+        self.hud.count_frame(now);
+        self.draw_hud(viewport, now);
+
         self.canvas.present();
+    }
+
+    /// Draw the HUD's status in the top left corner of the display and any message in the bottom left
+    fn draw_hud(&mut self, viewport: Rect, now: Instant) {
+        // Scaled so the text is a little smaller than CHIP-8 pixels
+        let unit = (viewport.height() / 160).max(1) as i32;
+        let left = viewport.left() + 2 * unit;
+
+        let status = self.hud.status();
+        for (i, line) in status.iter().enumerate() {
+            let top = viewport.top() + 2 * unit + i as i32 * hud::LINE_ADVANCE * unit;
+            self.draw_text(line, left, top, unit);
+        }
+
+        if let Some(message) = self.hud.message(now).map(str::to_string) {
+            let top = viewport.bottom() - (hud::GLYPH_HEIGHT + 2) * unit;
+            self.draw_text(&message, left, top, unit);
+        }
+
+        if let Some((items, selected)) = self.hud.menu().map(|(items, selected)| (items.to_vec(), selected)) {
+            self.draw_menu(viewport, unit, &items, selected);
+        }
+
+        if let Some(prompt) = self.hud.prompt().map(str::to_string) {
+            let unit = unit * 2;
+            let centre = viewport.center();
+            let left = centre.x() - hud::text_width(&prompt) * unit / 2;
+            let top = centre.y() - hud::GLYPH_HEIGHT * unit / 2;
+            self.draw_text(&prompt, left, top, unit);
+        }
+    }
+
+    /// Draw the menu over the whole display, with the selected item highlighted
+    fn draw_menu(&mut self, viewport: Rect, unit: i32, items: &[String], selected: usize) {
+        self.canvas.set_draw_color(colour(self.palette.background));
+        self.canvas.fill_rect(viewport).unwrap();
+
+        let left = viewport.left() + 2 * unit;
+        let mut top = viewport.top() + 2 * unit;
+        self.draw_text("SELECT A ROM", left, top, unit);
+        top += hud::LINE_ADVANCE * unit;
+
+        // Cut long titles off at the edge of the display
+        let max_chars = ((viewport.width() as i32 / unit - 4) / hud::CHAR_ADVANCE).max(1) as usize;
+        let (foreground, background) = (self.palette.foreground, self.palette.background);
+        for (i, item) in items.iter().enumerate() {
+            top += hud::LINE_ADVANCE * unit;
+            let text: String = item.chars().take(max_chars).collect();
+            if i == selected {
+                self.draw_text_coloured(&text, left, top, unit, background, foreground);
+            } else {
+                self.draw_text(&text, left, top, unit);
+            }
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, left: i32, top: i32, unit: i32) {
+        self.draw_text_coloured(text, left, top, unit, self.palette.foreground, self.palette.background);
+    }
+
+    fn draw_text_coloured(&mut self, text: &str, left: i32, top: i32, unit: i32, foreground: u32, background: u32) {
+        // A box in the background colour keeps the text readable over lit pixels
+        let width = (hud::text_width(text) + 2) * unit;
+        let height = (hud::GLYPH_HEIGHT + 2) * unit;
+        self.canvas.set_draw_color(colour(background));
+        self.canvas.fill_rect(Rect::new(left - unit, top - unit, width as u32, height as u32)).unwrap();
+
+        let pixels: Vec<Rect> = hud::text_pixels(text).iter()
+            .map(|(x, y)| Rect::new(left + x * unit, top + y * unit, unit as u32, unit as u32))
+            .collect();
+        self.canvas.set_draw_color(colour(foreground));
+        self.canvas.fill_rects(&pixels).unwrap();
+        self.canvas.set_draw_color(Color::BLACK);
+    }
+
+    /// Draw a pause, fast forward or rewind symbol in the top right corner of the display
+    fn draw_indicator(&mut self, viewport: Rect) {
+        // Sized in CHIP-8 pixels so it scales with the window
+        let unit = (viewport.height() / self.texture_size.1).max(1) as i32;
+        let right = viewport.right() - unit;
+        let top = viewport.top() + unit;
+
+        let rects: Vec<Rect> = match self.indicator {
+            Indicator::None => return,
+            Indicator::Paused => vec![
+                Rect::new(right - 4 * unit, top, unit as u32, 5 * unit as u32),
+                Rect::new(right - 2 * unit, top, unit as u32, 5 * unit as u32),
+            ],
+            Indicator::FastForward => (0..3)
+                .flat_map(|column| {
+                    // Two arrows, each narrowing from five pixels tall to one
+                    let height = 5 - 2 * column;
+                    vec![
+                        Rect::new(right - 7 * unit + column * unit, top + column * unit, unit as u32, (height * unit) as u32),
+                        Rect::new(right - 4 * unit + column * unit, top + column * unit, unit as u32, (height * unit) as u32),
+                    ]
+                })
+                .collect(),
+            Indicator::Rewind => (0..3)
+                .flat_map(|column| {
+                    // The fast forward arrows flipped, widening from one pixel tall to five
+                    let height = 1 + 2 * column;
+                    vec![
+                        Rect::new(right - 7 * unit + column * unit, top + (2 - column) * unit, unit as u32, (height * unit) as u32),
+                        Rect::new(right - 4 * unit + column * unit, top + (2 - column) * unit, unit as u32, (height * unit) as u32),
+                    ]
+                })
+                .collect(),
+        };
+
+        // Outline it in the background colour so it shows up over lit pixels
+        let outline: Vec<Rect> = rects.iter()
+            .map(|r| Rect::new(r.x() - unit, r.y() - unit, r.width() + 2 * unit as u32, r.height() + 2 * unit as u32))
+            .collect();
+        self.canvas.set_draw_color(colour(self.palette.background));
+        self.canvas.fill_rects(&outline).unwrap();
+        self.canvas.set_draw_color(colour(self.palette.foreground));
+        self.canvas.fill_rects(&rects).unwrap();
+        self.canvas.set_draw_color(Color::BLACK);
+    }
 
-        match sound_state {
-            SoundState::On => self.audio_device.resume(),
-            SoundState::Off => self.audio_device.pause(),
-        }
-    }
-
-    fn get_keycode(keycode: &Option<Keycode>) -> Option<usize> {
-        match keycode {
-            Some(Keycode::X) => Some(0),
-            Some(Keycode::Num1) => Some(1),
-            Some(Keycode::Num2) => Some(2),
-            Some(Keycode::Num3) => Some(3),
-            Some(Keycode::Q) => Some(4),
-            Some(Keycode::W) => Some(5),
-            Some(Keycode::E) => Some(6),
-            Some(Keycode::A) => Some(7),
-            Some(Keycode::S) => Some(8),
-            Some(Keycode::D) => Some(9),
-            Some(Keycode::Z) => Some(0xA),
-            Some(Keycode::C) => Some(0xB),
-            Some(Keycode::Num4) => Some(0xC),
-            Some(Keycode::R) => Some(0xD),
-            Some(Keycode::F) => Some(0xE),
-            Some(Keycode::V) => Some(0xF),
+    /// Pass the sound timer changes from the last frame on to the audio backend, which plays them
+    /// with the same timing as they had in emulated time.
+    pub fn play_sound(&mut self, events: &[SoundEvent], samples: &[f32]) -> io::Result<()> {
+        self.audio.play(events, samples)
+    }
+
+    pub fn finish_sound(&mut self) -> io::Result<()> {
+        self.audio.finish()
+    }
+
+    /// Bind CHIP-8 keys (0x0-0xF) on the keypad and the second keypad to keyboard keys by their
+    /// SDL name, e.g. "X" or "Space". This replaces any earlier bindings, keys without a binding
+    /// use the default layout, which the second keypad doesn't have.
+    pub fn set_key_bindings(&mut self, bindings: &[(usize, &str)], second_bindings: &[(usize, &str)]) -> Result<(), String> {
+        let (keymap, second_keymap) = keymaps(&keycodes(bindings)?, &keycodes(second_bindings)?);
+        self.keymap = keymap;
+        self.second_keymap = second_keymap;
+        Ok(())
+    }
+
+    fn menu_action(event: &Event) -> Option<MenuAction> {
+        match event {
+            Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+                Keycode::Up => Some(MenuAction::Up),
+                Keycode::Down => Some(MenuAction::Down),
+                Keycode::PageUp => Some(MenuAction::PageUp),
+                Keycode::PageDown => Some(MenuAction::PageDown),
+                Keycode::Return | Keycode::KpEnter => Some(MenuAction::Select),
+                Keycode::Escape => Some(MenuAction::Close),
+                _ => None,
+            },
+            Event::ControllerButtonDown { button, .. } => match button {
+                Button::DPadUp => Some(MenuAction::Up),
+                Button::DPadDown => Some(MenuAction::Down),
+                Button::LeftShoulder => Some(MenuAction::PageUp),
+                Button::RightShoulder => Some(MenuAction::PageDown),
+                Button::A => Some(MenuAction::Select),
+                Button::B => Some(MenuAction::Close),
+                _ => None,
+            },
             _ => None,
         }
     }
 
-    pub fn process_input(&self, keys: &mut [u8]) -> bool {
-        let mut event_pump = self.context.event_pump().unwrap();
-        let mut quit = false;
+    fn get_keycode(keymap: &Keymap, keycode: &Option<Keycode>) -> Option<usize> {
+        keycode.and_then(|keycode| keymap.iter().position(|k| *k == Some(keycode)))
+    }
+
+    /// Update the keypads from keyboard events, returning any hotkeys that were pressed
+    pub fn process_input(&mut self, keys: &mut [u8], second_keys: &mut [u8]) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let mut toggle_fullscreen = false;
+
+        for event in self.event_pump.poll_iter() {
+            if self.menu_open {
+                if let Some(action) = Self::menu_action(&event) {
+                    actions.push(Action::Menu(action));
+                    continue;
+                }
+            }
+
+            // CHIP-8 keys take precedence, in case they're bound to any of the hotkeys
+            let key = match &event {
+                Event::KeyDown { keycode, .. } | Event::KeyUp { keycode, .. } => {
+                    match (Self::get_keycode(&self.keymap, keycode), Self::get_keycode(&self.second_keymap, keycode)) {
+                        (Some(k), _) => Some((&mut *keys, k)),
+                        (None, Some(k)) => Some((&mut *second_keys, k)),
+                        (None, None) => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some((keypad, k)) = key {
+                match event {
+                    Event::KeyDown { .. } if self.menu_open => {}
+                    Event::KeyDown { .. } => keypad[k] = 1,
+                    _ => keypad[k] = 0,
+                }
+                continue;
+            }
 
-        for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    quit = true;
+                    actions.push(Action::Quit);
                 }
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(k) = Self::get_keycode(&keycode) {
-                        keys[k] = 1;
-                    }
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    let visible = self.hud.visible();
+                    self.hud.set_visible(!visible);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                    toggle_fullscreen = !toggle_fullscreen;
+                }
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } |
+                Event::KeyDown { keycode: Some(Keycode::Pause), repeat: false, .. } => {
+                    actions.push(Action::TogglePause);
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(k) = Self::get_keycode(&keycode) {
-                        keys[k] = 0;
+                Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => {
+                    actions.push(Action::Reset);
+                }
+                Event::KeyDown { keycode: Some(Keycode::Equals), .. } |
+                Event::KeyDown { keycode: Some(Keycode::KpPlus), .. } => {
+                    actions.push(Action::SpeedUp);
+                }
+                Event::KeyDown { keycode: Some(Keycode::Minus), .. } |
+                Event::KeyDown { keycode: Some(Keycode::KpMinus), .. } => {
+                    actions.push(Action::SpeedDown);
+                }
+                Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                    actions.push(Action::FastForward(true));
+                }
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    actions.push(Action::FastForward(false));
+                }
+                Event::KeyDown { keycode: Some(Keycode::Backquote), repeat: false, .. } => {
+                    actions.push(Action::Rewind(true));
+                }
+                Event::KeyUp { keycode: Some(Keycode::Backquote), .. } => {
+                    actions.push(Action::Rewind(false));
+                }
+                Event::DropFile { filename, .. } => {
+                    actions.push(Action::LoadRom(PathBuf::from(filename)));
+                }
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } |
+                Event::ControllerButtonDown { button: Button::Start, .. } |
+                Event::ControllerButtonDown { button: Button::Back, .. } => {
+                    actions.push(Action::ToggleMenu);
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(controller) = self.controller_subsystem.as_ref().and_then(|c| c.open(which).ok()) {
+                        self.controllers.push(controller);
                     }
                 }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|controller| controller.instance_id() != which);
+                }
                 _ => {}
             }
         };
 
-        quit
+        if toggle_fullscreen {
+            self.toggle_fullscreen();
+        }
+
+        actions
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_moves_rebound_keys_off_their_defaults() {
+        // Q is 4 by default, binding it to 5 leaves 4 unbound rather than shadowing 5
+        let (keymap, _) = keymaps(&[(0x5, Keycode::Q)], &[]);
+        assert_eq!(Platform::get_keycode(&keymap, &Some(Keycode::Q)), Some(0x5));
+        assert_eq!(keymap[0x4], None);
+        assert_eq!(Platform::get_keycode(&keymap, &Some(Keycode::W)), None);
+        assert_eq!(Platform::get_keycode(&keymap, &Some(Keycode::X)), Some(0x0));
+    }
+
+    #[test]
+    fn it_binds_the_second_keypad() {
+        // Nothing's bound to it by default, and binding a key to it takes it off the first
+        let (keymap, second) = keymaps(&[], &[(0x0, Keycode::Num1), (0xA, Keycode::Kp0)]);
+        assert_eq!(second.iter().flatten().count(), 2);
+        assert_eq!(Platform::get_keycode(&second, &Some(Keycode::Kp0)), Some(0xA));
+        assert_eq!(Platform::get_keycode(&keymap, &Some(Keycode::Num1)), None);
+        assert_eq!(Platform::get_keycode(&second, &Some(Keycode::Num1)), Some(0x0));
+    }
+}
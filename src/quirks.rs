@@ -0,0 +1,199 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviours that differ between CHIP-8 interpreters, ROMs written for one often break on another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// FX55 and FX65 leave I pointing past the last register stored or loaded.
+    pub memory: bool,
+    /// 8XY6 and 8XYE shift VX in place instead of shifting VY into VX.
+    pub shifting: bool,
+    /// BXNN jumps to XNN + VX instead of NNN + V0.
+    pub jumping: bool,
+    /// FX0A waits for a key to be pressed and released, beeping while it's held, instead of
+    /// finishing as soon as any key is down.
+    pub key_release: bool,
+    /// DXYN waits for the vertical blank interrupt before drawing, so at most one sprite is
+    /// drawn per frame.
+    pub display_wait: bool,
+    /// DXYN clips sprites at the edges of the screen instead of wrapping them around, only the
+    /// starting position wraps.
+    pub clipping: bool,
+}
+
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter.
+    pub const VIP: Quirks = Quirks {
+        vf_reset: true,
+        memory: true,
+        shifting: false,
+        jumping: false,
+        key_release: true,
+        display_wait: true,
+        clipping: true,
+    };
+
+    /// The behaviour of SUPER-CHIP 1.1 on the HP48.
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory: false,
+        shifting: true,
+        jumping: true,
+        key_release: false,
+        display_wait: false,
+        clipping: true,
+    };
+
+    /// What most modern interpreters (and most ROMs written for them) expect.
+    pub const MODERN: Quirks = Quirks {
+        vf_reset: false,
+        memory: false,
+        shifting: true,
+        jumping: false,
+        key_release: false,
+        display_wait: false,
+        clipping: false,
+    };
+
+    const PRESETS: [(&str, Quirks); 3] = [
+        ("vip", Quirks::VIP),
+        ("schip", Quirks::SCHIP),
+        ("modern", Quirks::MODERN),
+    ];
+
+    const NAMES: [&str; 7] = ["vf_reset", "memory", "shifting", "jumping", "key_release", "display_wait", "clipping"];
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "vf_reset" => Some(&mut self.vf_reset),
+            "memory" => Some(&mut self.memory),
+            "shifting" => Some(&mut self.shifting),
+            "jumping" => Some(&mut self.jumping),
+            "key_release" => Some(&mut self.key_release),
+            "display_wait" => Some(&mut self.display_wait),
+            "clipping" => Some(&mut self.clipping),
+            _ => None,
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        let mut quirks = *self;
+        *quirks.flag_mut(name).unwrap()
+    }
+
+    /// A short description to show, the closest preset followed by the quirks that differ from
+    /// it, e.g. `vip -memory +jumping`
+    pub fn summary(&self) -> String {
+        let differences = |preset: &Quirks| -> Vec<String> {
+            Quirks::NAMES.iter()
+                .filter(|n| self.flag(n) != preset.flag(n))
+                .map(|n| format!("{}{}", if self.flag(n) { '+' } else { '-' }, n))
+                .collect()
+        };
+
+        let (name, preset) = Quirks::PRESETS.iter().min_by_key(|(_, preset)| differences(preset).len()).unwrap();
+        let mut words = vec![name.to_string()];
+        words.extend(differences(preset));
+        words.join(" ")
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Parse a comma separated list of a preset and/or individual quirks, e.g. `vip,-memory`.
+    /// A bare or `+` prefixed quirk name enables it and a `-` prefix disables it.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            if let Some((_, preset)) = Quirks::PRESETS.iter().find(|(name, _)| *name == item) {
+                quirks = *preset;
+                continue;
+            }
+
+            let (name, enabled) = match item.as_bytes()[0] {
+                b'-' => (&item[1..], false),
+                b'+' => (&item[1..], true),
+                _ => (item, true),
+            };
+
+            match quirks.flag_mut(name) {
+                Some(flag) => *flag = enabled,
+                None => return Err(format!(
+                    "unknown quirk '{}', expected one of {} or a preset ({})",
+                    name,
+                    Quirks::NAMES.join(", "),
+                    Quirks::PRESETS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "),
+                )),
+            }
+        }
+
+        Ok(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = Quirks::PRESETS.iter().find(|(_, q)| q == self) {
+            return write!(f, "{}", name);
+        }
+
+        // Spell out every quirk so the result parses back to the same set
+        let flags: Vec<String> = Quirks::NAMES.iter()
+            .map(|n| format!("{}{}", if self.flag(n) { '+' } else { '-' }, n))
+            .collect();
+        write!(f, "{}", flags.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_presets() {
+        assert_eq!("vip".parse(), Ok(Quirks::VIP));
+        assert_eq!("schip".parse(), Ok(Quirks::SCHIP));
+        assert_eq!("".parse(), Ok(Quirks::default()));
+    }
+
+    #[test]
+    fn it_parses_individual_quirks() {
+        let quirks: Quirks = "vip,-memory,+jumping".parse().unwrap();
+        assert!(quirks.vf_reset);
+        assert!(!quirks.memory);
+        assert!(!quirks.shifting);
+        assert!(quirks.jumping);
+
+        assert!("vip,warp".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn it_displays_quirks() {
+        assert_eq!(Quirks::VIP.to_string(), "vip");
+
+        let quirks: Quirks = "vip,-memory".parse().unwrap();
+        assert_eq!(quirks.to_string(), "+vf_reset,-memory,-shifting,-jumping,+key_release,+display_wait,+clipping");
+        assert_eq!(quirks.to_string().parse(), Ok(quirks));
+    }
+
+    #[test]
+    fn it_summarises_quirks() {
+        assert_eq!(Quirks::MODERN.summary(), "modern");
+
+        let quirks: Quirks = "vip,-memory,+jumping".parse().unwrap();
+        assert_eq!(quirks.summary(), "vip -memory +jumping");
+
+        let quirks: Quirks = "modern,+vf_reset,+key_release,+display_wait".parse().unwrap();
+        assert_eq!(quirks.summary(), "vip -memory +shifting -clipping");
+    }
+}
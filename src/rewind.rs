@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+
+use crate::Chip8;
+use crate::display::Display;
+use crate::machine::{ColourOverlay, MegaChip};
+use crate::vip::Vip;
+
+/// Runs of repeated values as (count, value) pairs
+fn compress<T: Copy + PartialEq>(data: &[T]) -> Vec<(u16, T)> {
+    let mut runs: Vec<(u16, T)> = Vec::new();
+    for value in data {
+        match runs.last_mut() {
+            Some((count, last)) if last == value && *count < u16::MAX => *count += 1,
+            _ => runs.push((1, *value)),
+        }
+    }
+    runs
+}
+
+fn decompress<T: Copy>(runs: &[(u16, T)], out: &mut [T]) {
+    let mut start = 0;
+    for (count, value) in runs {
+        let end = start + *count as usize;
+        out[start..end].fill(*value);
+        start = end;
+    }
+}
+
+/// The state of a `Chip8` at one moment. Memory and the display are run length encoded as
+/// they're mostly empty, which makes a snapshot a few kilobytes at most. An emulated VIP and
+/// MegaChip's colour display are kept whole.
+#[derive(Clone)]
+pub struct Snapshot {
+    registers: [u8; 16],
+    memory_size: usize,
+    memory: Vec<(u16, u8)>,
+    index: u32,
+    pc: u16,
+    stack: [u16; 16],
+    sp: u8,
+    delay_timer: u8,
+    sound_timer: u8,
+    previous_keypad: [u8; 16],
+    held_key: Option<usize>,
+    waiting_for_vblank: bool,
+    vip_cycles: u32,
+    video_size: (usize, usize),
+    video_coloured: bool,
+    video: Vec<(u16, u32)>,
+    rand_gen: StdRng,
+    colours: ColourOverlay,
+    waiting_for_timer: bool,
+    vip: Option<Box<Vip>>,
+    mega: Option<Box<MegaChip>>,
+}
+
+impl Chip8 {
+    /// Save the state of the machine. The keypad and quirks aren't part of it, they're settings
+    /// rather than state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            memory_size: self.memory.len(),
+            memory: compress(&self.memory),
+            index: self.index,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            previous_keypad: self.previous_keypad,
+            held_key: self.held_key,
+            waiting_for_vblank: self.waiting_for_vblank,
+            vip_cycles: self.vip_cycles,
+            video_size: self.video.size(),
+            video_coloured: self.video.is_coloured(),
+            video: compress(&self.video),
+            rand_gen: self.rand_gen.clone(),
+            colours: self.colours,
+            waiting_for_timer: self.waiting_for_timer,
+            vip: self.vip.clone(),
+            mega: self.mega.clone(),
+        }
+    }
+
+    /// Go back to an earlier state. The frame count carries on so the sound events stay in order,
+    /// and if the buzzer changes it's reported at the start of the next frame.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let sound = self.sound();
+
+        self.registers = snapshot.registers;
+        self.memory.resize(snapshot.memory_size, 0);
+        decompress(&snapshot.memory, &mut self.memory);
+        self.index = snapshot.index;
+        self.pc = snapshot.pc;
+        self.stack = snapshot.stack;
+        self.sp = snapshot.sp;
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.previous_keypad = snapshot.previous_keypad;
+        self.held_key = snapshot.held_key;
+        self.waiting_for_vblank = snapshot.waiting_for_vblank;
+        self.vip_cycles = snapshot.vip_cycles;
+        if self.video.size() != snapshot.video_size || self.video.is_coloured() != snapshot.video_coloured {
+            let (width, height) = snapshot.video_size;
+            self.video = if snapshot.video_coloured { Display::coloured(width, height) } else { Display::new(width, height) };
+        }
+        decompress(&snapshot.video, &mut self.video);
+        self.rand_gen = snapshot.rand_gen.clone();
+        self.colours = snapshot.colours;
+        self.waiting_for_timer = snapshot.waiting_for_timer;
+        self.vip = snapshot.vip.clone();
+        self.mega = snapshot.mega.clone();
+
+        self.sound_events.clear();
+        self.samples.clear();
+        self.record_sound(sound, 0.0);
+    }
+}
+
+/// A ring buffer of snapshots taken every few frames, the oldest are dropped once it's full.
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    interval: u32,
+    frames: u32,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots, one every `interval` frames
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Rewind { snapshots: VecDeque::with_capacity(capacity), capacity, interval: interval.max(1), frames: 0 }
+    }
+
+    /// Call after every frame run, a snapshot is taken when one is due
+    pub fn record(&mut self, chip8: &Chip8) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(chip8.snapshot());
+    }
+
+    /// Take the latest snapshot out of the buffer, restoring it goes back one step
+    pub fn step_back(&mut self) -> Option<Snapshot> {
+        self.frames = 0;
+        self.snapshots.pop_back()
+    }
+
+    /// Forget the history, e.g. when a different ROM is loaded
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.snapshots.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws a random sprite at a random position forever
+    const RANDOM_SPRITES: [u8; 10] = [
+        0xC0, 0x3F,  // V0 = random & 0x3F
+        0xC1, 0x1F,  // V1 = random & 0x1F
+        0xC2, 0x0F,  // V2 = random & 0x0F
+        0xF2, 0x29,  // I = font character V2
+        0xD0, 0x15,  // draw it at V0, V1
+    ];
+
+    fn chip8() -> Chip8 {
+        let mut rom = RANDOM_SPRITES.to_vec();
+        rom.extend([0x12, 0x00]);
+
+        let mut chip8 = Chip8::new();
+        chip8.set_seed(1);
+        chip8.load_bytes(&rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn it_compresses_runs() {
+        let runs = compress(&[0, 0, 0, 7, 0]);
+        assert_eq!(runs, vec![(3, 0), (1, 7), (1, 0)]);
+
+        let mut out = [9; 5];
+        decompress(&runs, &mut out);
+        assert_eq!(out, [0, 0, 0, 7, 0]);
+
+        assert_eq!(compress(&[1u8; 70000]), vec![(u16::MAX, 1), (4465, 1)]);
+    }
+
+    #[test]
+    fn it_restores_snapshots() {
+        let mut chip8 = chip8();
+        chip8.run_frame(50);
+        let snapshot = chip8.snapshot();
+
+        chip8.run_frame(50);
+        let video = chip8.video.clone();
+        let pc = chip8.pc;
+
+        // Random numbers come out the same after going back, so the display does too
+        chip8.restore(&snapshot);
+        assert_ne!(chip8.video, video);
+        chip8.run_frame(50);
+        assert_eq!(chip8.video, video);
+        assert_eq!(chip8.pc, pc);
+    }
+
+    #[test]
+    fn it_reports_the_buzzer_stopping_on_restore() {
+        let mut chip8 = chip8();
+        let snapshot = chip8.snapshot();
+        chip8.sound_timer = 10;
+        chip8.run_frame(1);
+
+        chip8.restore(&snapshot);
+        chip8.run_frame(1);
+        assert!(matches!(chip8.sound_events(), [event] if !event.on && event.offset == 0.0));
+    }
+
+    #[test]
+    fn it_keeps_a_limited_history() {
+        let mut chip8 = chip8();
+        let mut rewind = Rewind::new(3, 2);
+
+        for _ in 0..10 {
+            chip8.run_frame(5);
+            rewind.record(&chip8);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        // The latest snapshot is from the tenth frame
+        let latest = rewind.step_back().unwrap();
+        let video = chip8.video.clone();
+        chip8.restore(&latest);
+        assert_eq!(chip8.video, video);
+
+        rewind.step_back();
+        rewind.step_back();
+        assert!(rewind.step_back().is_none());
+
+        rewind.record(&chip8);
+        assert!(rewind.is_empty());
+    }
+}
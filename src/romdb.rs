@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::Settings;
+use crate::machine::Machine;
+use crate::palette::{parse_colour, Palette};
+use crate::quirks::Quirks;
+
+const PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+const PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+
+/// Platforms running on the COSMAC VIP, whose FX0A waits for the key to be released.
+/// The database doesn't record this quirk, so it goes by the platform.
+const VIP_PLATFORMS: [&str; 3] = ["originalChip8", "hybridVIP", "chip8x"];
+
+/// A set of quirks as the database describes them, any of which may be left unset.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct QuirkRecord {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    jump: Option<bool>,
+    logic: Option<bool>,
+    vblank: Option<bool>,
+    wrap: Option<bool>,
+}
+
+impl QuirkRecord {
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift { quirks.shifting = shift; }
+        if let Some(jump) = self.jump { quirks.jumping = jump; }
+        if let Some(logic) = self.logic { quirks.vf_reset = logic; }
+        if let Some(vblank) = self.vblank { quirks.display_wait = vblank; }
+        if let Some(wrap) = self.wrap { quirks.clipping = !wrap; }
+
+        // Incrementing I by X rather than X + 1 is close enough to treat as incrementing
+        if let Some(increment) = self.memory_increment_by_x {
+            if increment { quirks.memory = true; }
+        }
+        if let Some(unchanged) = self.memory_leave_i_unchanged {
+            quirks.memory = !unchanged;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlatformRecord {
+    id: String,
+    name: String,
+    default_tickrate: Option<usize>,
+    #[serde(default)]
+    quirks: QuirkRecord,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct ColoursRecord {
+    pixels: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RomRecord {
+    platforms: Vec<String>,
+    quirky_platforms: HashMap<String, QuirkRecord>,
+    tickrate: Option<usize>,
+    colors: Option<ColoursRecord>,
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ProgramRecord {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, RomRecord>,
+}
+
+/// What is known about a ROM, from the database if it's listed there.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub hash: String,
+    pub title: Option<String>,
+    /// Name of the platform the ROM was written for
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    /// The variant to run the ROM as, if it's not plain CHIP-8
+    pub machine: Option<Machine>,
+    /// Recommended instructions per frame
+    pub tickrate: Option<usize>,
+    pub palette: Option<Palette>,
+    /// Which CHIP-8 key does what, e.g. "up" => 5
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl RomInfo {
+    /// The recommended settings, for the config file and command line to override
+    pub fn settings(&self) -> Settings {
+        Settings {
+            cycles_per_frame: self.tickrate,
+            quirks: self.quirks,
+            machine: self.machine,
+            palette: self.palette,
+            ..Settings::default()
+        }
+    }
+}
+
+/// ROM metadata in the format of the community CHIP-8 database
+/// (https://github.com/chip-8/chip-8-database), keyed by the SHA-1 hash of each ROM.
+pub struct RomDatabase {
+    platforms: Vec<PlatformRecord>,
+    programs: Vec<ProgramRecord>,
+    hashes: HashMap<String, usize>,
+}
+
+impl RomDatabase {
+    /// The database compiled into the executable
+    pub fn builtin() -> RomDatabase {
+        RomDatabase::parse(PLATFORMS, PROGRAMS, HASHES).expect("built-in ROM database is invalid")
+    }
+
+    /// `$XDG_DATA_HOME/chip8-rs/chip-8-database` or the platform equivalent
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("chip8-rs").join("chip-8-database"))
+    }
+
+    /// Load the `platforms.json`, `programs.json` and `sha1-hashes.json` files from a directory.
+    /// A missing `platforms.json` falls back to the built-in one.
+    pub fn load_dir(dir: &Path) -> Result<RomDatabase, String> {
+        let read = |name: &str| fs::read_to_string(dir.join(name))
+            .map_err(|e| format!("could not read ROM database file '{}': {}", dir.join(name).display(), e));
+
+        let platforms = if dir.join("platforms.json").exists() { read("platforms.json")? } else { PLATFORMS.to_string() };
+        RomDatabase::parse(&platforms, &read("programs.json")?, &read("sha1-hashes.json")?)
+            .map_err(|e| format!("invalid ROM database in '{}': {}", dir.display(), e))
+    }
+
+    pub fn parse(platforms: &str, programs: &str, hashes: &str) -> Result<RomDatabase, String> {
+        let platforms = serde_json::from_str(platforms).map_err(|e| format!("platforms.json: {}", e))?;
+        let programs: Vec<ProgramRecord> = serde_json::from_str(programs).map_err(|e| format!("programs.json: {}", e))?;
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes).map_err(|e| format!("sha1-hashes.json: {}", e))?;
+
+        if let Some((hash, _)) = hashes.iter().find(|(_, i)| **i >= programs.len()) {
+            return Err(format!("sha1-hashes.json: {} refers to a program that doesn't exist", hash));
+        }
+
+        let hashes = hashes.into_iter().map(|(hash, i)| (hash.to_lowercase(), i)).collect();
+        Ok(RomDatabase { platforms, programs, hashes })
+    }
+
+    /// Look up a ROM by its SHA-1 hash, see [`crate::rom_hash`].
+    pub fn lookup(&self, hash: &str) -> RomInfo {
+        let hash = hash.to_lowercase();
+        let mut info = RomInfo { hash: hash.clone(), ..RomInfo::default() };
+
+        let program = match self.hashes.get(&hash) {
+            Some(i) => &self.programs[*i],
+            None => return info,
+        };
+        info.title = Some(program.title.clone());
+
+        let rom = match program.roms.iter().find(|(h, _)| h.eq_ignore_ascii_case(&hash)) {
+            Some((_, rom)) => rom,
+            None => return info,
+        };
+
+        let platform = rom.platforms.first()
+            .and_then(|id| self.platforms.iter().find(|p| p.id == *id));
+        if let Some(platform) = platform {
+            let mut quirks = Quirks { key_release: VIP_PLATFORMS.contains(&platform.id.as_str()), ..Quirks::default() };
+            platform.quirks.apply(&mut quirks);
+            if let Some(overrides) = rom.quirky_platforms.get(&platform.id) {
+                overrides.apply(&mut quirks);
+            }
+
+            info.platform = Some(platform.name.clone());
+            info.quirks = Some(quirks);
+            info.machine = match platform.id.as_str() {
+                "chip8x" => Some(Machine::Chip8X),
+                "megachip8" => Some(Machine::MegaChip),
+                _ => None,
+            };
+        }
+
+        info.tickrate = rom.tickrate.or_else(|| platform.and_then(|p| p.default_tickrate));
+        info.palette = rom.colors.as_ref().and_then(|colours| match colours.pixels.as_slice() {
+            [background, foreground, ..] => Some(Palette {
+                foreground: parse_colour(foreground).ok()?,
+                background: parse_colour(background).ok()?,
+            }),
+            _ => None,
+        });
+        info.keys = rom.keys.clone();
+
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Test Game",
+            "roms": {
+                "0123456789abcdef0123456789abcdef01234567": {
+                    "file": "test.ch8",
+                    "platforms": ["originalChip8"],
+                    "quirkyPlatforms": { "originalChip8": { "logic": false } },
+                    "colors": { "pixels": ["#000080", "#FFFF00"] },
+                    "keys": { "left": 4, "right": 6 }
+                }
+            }
+        },
+        {
+            "title": "Other Game",
+            "roms": {
+                "89abcdef0123456789abcdef0123456789abcdef": {
+                    "platforms": ["superchip"],
+                    "tickrate": 50
+                }
+            }
+        },
+        {
+            "title": "Colour Game",
+            "roms": {
+                "fedcba9876543210fedcba9876543210fedcba98": {
+                    "platforms": ["chip8x"]
+                }
+            }
+        }
+    ]"##;
+
+    const HASHES: &str = r#"{
+        "0123456789ABCDEF0123456789ABCDEF01234567": 0,
+        "89abcdef0123456789abcdef0123456789abcdef": 1,
+        "fedcba9876543210fedcba9876543210fedcba98": 2
+    }"#;
+
+    #[test]
+    fn it_parses_the_builtin_database() {
+        let database = RomDatabase::builtin();
+        assert!(database.platforms.iter().any(|p| p.id == "originalChip8"));
+    }
+
+    #[test]
+    fn it_looks_up_roms() {
+        let database = RomDatabase::parse(PLATFORMS, PROGRAMS, HASHES).unwrap();
+
+        let info = database.lookup("0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(info.title.as_deref(), Some("Test Game"));
+        assert_eq!(info.platform.as_deref(), Some("Cosmac VIP CHIP-8"));
+        assert_eq!(info.quirks, Some(Quirks { vf_reset: false, ..Quirks::VIP }));
+        assert_eq!(info.tickrate, Some(15));
+        assert_eq!(info.palette, Some(Palette { foreground: 0xFFFF00FF, background: 0x000080FF }));
+        assert_eq!(info.keys.get("left"), Some(&4));
+
+        let info = database.lookup("89ABCDEF0123456789ABCDEF0123456789ABCDEF");
+        assert_eq!(info.quirks, Some(Quirks::SCHIP));
+        assert_eq!(info.tickrate, Some(50));
+        assert_eq!(info.palette, None);
+        assert_eq!(info.machine, None);
+
+        let info = database.lookup("fedcba9876543210fedcba9876543210fedcba98");
+        assert_eq!(info.machine, Some(Machine::Chip8X));
+        assert_eq!(info.settings().machine, Some(Machine::Chip8X));
+    }
+
+    #[test]
+    fn it_returns_nothing_for_unknown_roms() {
+        let database = RomDatabase::parse(PLATFORMS, PROGRAMS, HASHES).unwrap();
+
+        let info = database.lookup("ffffffffffffffffffffffffffffffffffffffff");
+        assert_eq!(info, RomInfo { hash: "ffffffffffffffffffffffffffffffffffffffff".to_string(), ..RomInfo::default() });
+        assert_eq!(info.settings(), Settings::default());
+    }
+
+    #[test]
+    fn it_rejects_dangling_hashes() {
+        assert!(RomDatabase::parse(PLATFORMS, "[]", HASHES).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Rate at which frames are presented and the delay and sound timers count down.
+pub const FRAME_RATE: u32 = 60;
+
+/// Number of frames the limiter may fall behind before it gives up catching up.
+const MAX_FRAMES_BEHIND: u32 = 4;
+
+/// Paces the main loop to a fixed frame rate by sleeping away the rest of each frame.
+pub struct FrameLimiter {
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(frame_rate: u32) -> Self {
+        FrameLimiter {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Block until the next frame is due.
+    pub fn wait(&mut self) {
+        self.next_frame += self.frame_duration;
+
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration * MAX_FRAMES_BEHIND {
+            // We fell too far behind (e.g. the window was being dragged), so don't try to
+            // run a burst of frames to make up for it
+            self.next_frame = now;
+        }
+    }
+}
+
+/// How long instructions take to run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    /// Every instruction takes the same time, with a fixed number run each frame
+    Fixed,
+    /// Each instruction takes as many machine cycles as it did on the COSMAC VIP, and each frame
+    /// runs as many instructions as fit in the cycles the VIP had to spare
+    Vip,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::Fixed
+    }
+}
+
+impl Timing {
+    const NAMES: [(&str, Timing); 2] = [
+        ("fixed", Timing::Fixed),
+        ("vip", Timing::Vip),
+    ];
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Timing::NAMES.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, timing)| *timing)
+            .ok_or_else(|| format!(
+                "unknown timing '{}', expected one of {}",
+                s,
+                Timing::NAMES.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", "),
+            ))
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Timing::NAMES.iter().find(|(_, t)| t == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+/// COSMAC VIP machine cycles per 60 Hz frame, from its 1.76 MHz clock and 8 clocks per machine cycle
+const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// Machine cycles each frame spent on the display interrupt and the DMA feeding the video chip
+const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+
+/// Machine cycles each frame left for the CHIP-8 interpreter
+pub const VIP_INTERPRETER_CYCLES: u32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+
+/// Machine cycles the VIP interpreter takes to fetch and decode an instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// Extra machine cycles taken when a conditional skip is taken
+const VIP_SKIP_CYCLES: u32 = 4;
+
+/// Roughly how many machine cycles an instruction took on the COSMAC VIP, going by analyses of
+/// its interpreter. `registers` are as they were before the instruction ran.
+pub fn vip_cycles(opcode: u16, registers: &[u8; 16], skipped: bool) -> u32 {
+    let vx = registers[((opcode >> 8) & 0xF) as usize] as u32;
+    let x = (opcode >> 8) & 0xF;
+    let n = (opcode & 0xF) as u32;
+
+    let execute = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => 3078,
+            0x00EE => 10,
+            _ => 0,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10,
+        0x5 | 0x9 => 14,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        // Each row of the sprite is shifted into place a bit at a time
+        0xD => 26 + n * (46 + 8 * (vx % 8)),
+        0xE => 14,
+        0xF => match opcode & 0xFF {
+            0x1E => 16,
+            0x29 => 16,
+            // Each digit is found by repeated subtraction
+            0x33 => 80 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10),
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            _ => 10,
+        },
+        _ => 0,
+    };
+
+    VIP_FETCH_CYCLES + execute + if skipped { VIP_SKIP_CYCLES } else { 0 }
+}
+
+/// Instructions per frame after speeding up by a step of about a quarter
+pub fn faster(cycles_per_frame: usize) -> usize {
+    (cycles_per_frame * 5 / 4).max(cycles_per_frame + 1)
+}
+
+/// Instructions per frame after slowing down by a step, never stopping altogether
+pub fn slower(cycles_per_frame: usize) -> usize {
+    (cycles_per_frame * 4 / 5).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_paces_frames() {
+        let mut limiter = FrameLimiter::new(100);
+        let start = Instant::now();
+
+        (0..5).for_each(|_| limiter.wait());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn it_changes_speed_in_steps() {
+        assert_eq!(faster(10), 12);
+        assert_eq!(faster(1), 2);
+        assert_eq!(slower(12), 9);
+        assert_eq!(slower(1), 1);
+    }
+
+    #[test]
+    fn it_parses_timings() {
+        assert_eq!("vip".parse(), Ok(Timing::Vip));
+        assert_eq!("Fixed".parse(), Ok(Timing::Fixed));
+        assert!("pal".parse::<Timing>().is_err());
+        assert_eq!(Timing::Vip.to_string(), "vip");
+    }
+
+    #[test]
+    fn it_counts_vip_cycles() {
+        let mut registers = [0; 16];
+        registers[1] = 255;
+        registers[2] = 3;
+
+        assert_eq!(vip_cycles(0x6105, &registers, false), 46);
+        assert_eq!(vip_cycles(0x3100, &registers, true), vip_cycles(0x3100, &registers, false) + VIP_SKIP_CYCLES);
+
+        // Sprites take longer the taller they are and the further they're shifted
+        assert!(vip_cycles(0xD015, &registers, false) < vip_cycles(0xD01F, &registers, false));
+        assert!(vip_cycles(0xD015, &registers, false) < vip_cycles(0xD215, &registers, false));
+
+        // So does BCD of bigger numbers
+        assert!(vip_cycles(0xF033, &registers, false) < vip_cycles(0xF133, &registers, false));
+    }
+
+    #[test]
+    fn it_does_not_burst_after_a_stall() {
+        let mut limiter = FrameLimiter::new(100);
+        thread::sleep(Duration::from_millis(100));
+
+        // The missed frames are dropped rather than run back to back
+        limiter.wait();
+        let start = Instant::now();
+        limiter.wait();
+        assert!(start.elapsed() >= Duration::from_millis(9));
+    }
+}
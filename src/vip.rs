@@ -0,0 +1,331 @@
+use std::io;
+
+use crate::cdp1802::{Bus, Cdp1802};
+use crate::display::{LORES_HEIGHT, LORES_WIDTH};
+
+/// A 4K VIP, the interpreter's variables and the display buffer take up the top pages
+const RAM_SIZE: usize = 4096;
+/// Where programs start, after the interpreter
+const PROGRAM_ADDRESS: usize = 0x200;
+
+/// The 1861 draws 262 lines a frame, 14 machine cycles each
+const LINES_PER_FRAME: u32 = 262;
+const CYCLES_PER_LINE: u32 = 14;
+/// Lines 80-207 are the visible ones, each fetches 8 bytes by DMA after the CPU gets 6 cycles.
+/// That's a row of the interpreter's usual 64x32 display, each drawn on 4 lines.
+const DISPLAY_START: u32 = 80;
+const DISPLAY_LINES: u32 = 128;
+const DMA_BYTES: usize = LORES_WIDTH / 8;
+const CPU_CYCLES_PER_DISPLAY_LINE: u32 = CYCLES_PER_LINE - DMA_BYTES as u32;
+/// The interrupt comes two lines before the display starts, to give the handler time to set R0
+const INTERRUPT_LINE: u32 = DISPLAY_START - 2;
+/// EF1 is asserted for the four lines before the display and its last four lines
+const EF1_LINES: u32 = 4;
+
+/// The display interrupt handler, built in where the interpreter expects the monitor ROM's to be.
+/// The interpreter keeps the display buffer's page in RB.1 and its timers in R8, the delay timer
+/// in R8.1 and the sound timer in R8.0, with Q driving the buzzer.
+const INTERRUPT_RETURN: u16 = 0x8144;
+const INTERRUPT_HANDLER: [u8; 62] = [
+    // 8144: restore D and return, leaving R1 at the entry point for the next interrupt
+    0x72,             // LDXA
+    0x70,             // RET
+    // 8146: save T and D on the stack
+    0x22,             // DEC 2
+    0x78,             // SAV
+    0x22,             // DEC 2
+    0x52,             // STR 2
+    // point R0 at the display buffer
+    0xF8, 0x00,       // LDI 00
+    0xA0,             // PLO 0
+    0x9B,             // GHI B
+    0xB0,             // PHI 0
+    // wait for the display to start, then 3 instructions (6 cycles) before each line's DMA
+    0x34, 0x4F,       // 814F: B1 814F
+    0xE2,             // SEX 2
+    0xE2,             // SEX 2
+    // 8153: each row is drawn on 4 lines, so R0 goes back 8 bytes after the first 3 of them
+    0x80, 0xFF, 0x08, 0xA0,  // GLO 0, SMI 08, PLO 0
+    0x80, 0xFF, 0x08, 0xA0,
+    0x80, 0xFF, 0x08, 0xA0,
+    0xE2,             // SEX 2
+    0xE2,             // SEX 2
+    0x3C, 0x53,       // BN1 8153, until the last row
+    0x80, 0xFF, 0x08, 0xA0,
+    0x80, 0xFF, 0x08, 0xA0,
+    0x80, 0xFF, 0x08, 0xA0,
+    // 816F: count down the delay timer
+    0x98,             // GHI 8
+    0x32, 0x75,       // BZ 8175
+    0xFF, 0x01,       // SMI 01
+    0xB8,             // PHI 8
+    // 8175: count down the sound timer, the buzzer sounds while it's running
+    0x88,             // GLO 8
+    0x32, 0x7F,       // BZ 817F
+    0xFF, 0x01,       // SMI 01
+    0xA8,             // PLO 8
+    0x32, 0x7F,       // BZ 817F
+    0x7B,             // SEQ
+    0x38,             // SKP
+    0x7A,             // 817F: REQ
+    0x30, 0x44,       // BR 8144
+];
+
+/// The VIP's memory and I/O as the CPU sees them
+#[derive(Clone)]
+struct VipBus {
+    ram: [u8; RAM_SIZE],
+    keypad: [u8; 16],
+    /// The key OUT 2 selected, EF3 is asserted while it's pressed
+    key_latch: usize,
+    /// The 1861 is turned on by INP 1 and off by OUT 1
+    display_on: bool,
+    /// The line the 1861 is drawing
+    line: u32,
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 == 0 {
+            return self.ram[address as usize % RAM_SIZE];
+        }
+
+        // The 512 byte ROM repeats through the upper half of the address space
+        let offset = (address & 0x81FF).wrapping_sub(INTERRUPT_RETURN) as usize;
+        INTERRUPT_HANDLER.get(offset).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 == 0 {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.key_latch = (value & 0xF) as usize,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn flag(&mut self, flag: u8) -> bool {
+        match flag {
+            1 => {
+                let before = DISPLAY_START - EF1_LINES..DISPLAY_START;
+                let end = DISPLAY_START + DISPLAY_LINES;
+                self.display_on && (before.contains(&self.line) || (end - EF1_LINES..end).contains(&self.line))
+            }
+            3 => self.keypad[self.key_latch] != 0,
+            _ => false,
+        }
+    }
+}
+
+/// An RCA COSMAC VIP: a CDP1802, 4K of RAM, the hex keypad and the 1861 video chip, which takes
+/// the display buffer from memory by DMA while the picture is being drawn.
+#[derive(Clone)]
+pub struct Vip {
+    cpu: Cdp1802,
+    bus: VipBus,
+    /// Cycles the CPU ran past the end of the last line
+    line_cycles: u32,
+    /// When Q changed during the last frame, and what to
+    sound_changes: Vec<(f32, bool)>,
+}
+
+impl Vip {
+    /// A VIP with `interpreter` loaded at 0000, as if it had been read from tape, and the registers
+    /// set up the way the monitor leaves them when it runs a program: R0 is the program counter
+    /// and R1.1 is the last page of RAM.
+    pub fn new(interpreter: &[u8]) -> io::Result<Self> {
+        if interpreter.len() > PROGRAM_ADDRESS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the interpreter is {} bytes but should fit in {}", interpreter.len(), PROGRAM_ADDRESS),
+            ));
+        }
+
+        let mut ram = [0; RAM_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+
+        let mut cpu = Cdp1802::new();
+        cpu.r[1] = (RAM_SIZE as u16 - 1) & 0xFF00;
+
+        Ok(Vip {
+            cpu,
+            bus: VipBus { ram, keypad: [0; 16], key_latch: 0, display_on: false, line: 0 },
+            line_cycles: 0,
+            sound_changes: Vec::new(),
+        })
+    }
+
+    /// Load a CHIP-8 program where the interpreter runs it from
+    pub fn load(&mut self, program: &[u8]) -> io::Result<()> {
+        if program.len() > RAM_SIZE - PROGRAM_ADDRESS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("ROM is {} bytes but only {} bytes of memory are available", program.len(), RAM_SIZE - PROGRAM_ADDRESS),
+            ));
+        }
+
+        self.bus.ram[PROGRAM_ADDRESS..PROGRAM_ADDRESS + program.len()].copy_from_slice(program);
+        Ok(())
+    }
+
+    /// Whether the buzzer is sounding
+    pub fn sound(&self) -> bool {
+        self.cpu.q
+    }
+
+    /// The times Q changed during the last frame, as how far through the frame and whether it's on
+    pub fn sound_changes(&self) -> &[(f32, bool)] {
+        &self.sound_changes
+    }
+
+    /// Run the CPU until it's used up `cycles`, returning the number of instructions executed
+    fn run_cycles(&mut self, cycles: u32) -> usize {
+        let mut executed = 0;
+
+        while self.line_cycles < cycles {
+            let sound = self.cpu.q;
+            self.line_cycles += self.cpu.step(&mut self.bus);
+            executed += 1;
+
+            if self.cpu.q != sound {
+                let cycle = self.bus.line * CYCLES_PER_LINE + self.line_cycles;
+                let offset = cycle as f32 / (LINES_PER_FRAME * CYCLES_PER_LINE) as f32;
+                self.sound_changes.push((offset.min(1.0), self.cpu.q));
+            }
+        }
+
+        executed
+    }
+
+    /// Run a 60 Hz frame with the keys in `keypad` held, drawing into `video` as the 1861 would.
+    /// Returns the number of 1802 instructions executed.
+    pub fn run_frame(&mut self, keypad: &[u8; 16], video: &mut [u32]) -> usize {
+        self.bus.keypad = *keypad;
+        self.sound_changes.clear();
+        let mut executed = 0;
+
+        for line in 0..LINES_PER_FRAME {
+            self.bus.line = line;
+
+            if line == INTERRUPT_LINE && self.bus.display_on {
+                self.line_cycles += self.cpu.interrupt();
+            }
+
+            let display_line = line.wrapping_sub(DISPLAY_START);
+            if self.bus.display_on && display_line < DISPLAY_LINES {
+                executed += self.run_cycles(CPU_CYCLES_PER_DISPLAY_LINE);
+
+                let mut bytes = [0; DMA_BYTES];
+                for byte in bytes.iter_mut() {
+                    *byte = self.cpu.dma_out(&mut self.bus);
+                }
+                self.line_cycles += DMA_BYTES as u32;
+
+                // Rows are normally drawn on four lines each, the first of them is the one shown
+                let row = (display_line * LORES_HEIGHT as u32 / DISPLAY_LINES) as usize;
+                if display_line % (DISPLAY_LINES / LORES_HEIGHT as u32) == 0 {
+                    let pixels = &mut video[row * LORES_WIDTH..(row + 1) * LORES_WIDTH];
+                    for (i, pixel) in pixels.iter_mut().enumerate() {
+                        let lit = bytes[i / 8] & 0x80 >> (i % 8) != 0;
+                        *pixel = if lit { 0xFFFFFFFF } else { 0 };
+                    }
+                }
+            }
+
+            executed += self.run_cycles(CYCLES_PER_LINE);
+            self.line_cycles -= CYCLES_PER_LINE;
+        }
+
+        if !self.bus.display_on {
+            video.fill(0);
+        }
+
+        executed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets up the stack, display page and interrupt handler like the real interpreter does, then
+    /// turns the display on and loops forever
+    const INTERPRETER: [u8; 18] = [
+        0x91, 0xBB,       // GHI 1, PHI B: the display is the last page
+        0xFF, 0x01, 0xB2, // SMI 01, PHI 2: the stack is the page before
+        0xF8, 0xCF, 0xA2, // LDI CF, PLO 2
+        0xF8, 0x81, 0xB1, // LDI 81, PHI 1
+        0xF8, 0x46, 0xA1, // LDI 46, PLO 1: the interrupt handler
+        0xE2,             // SEX 2
+        0x69,             // INP 1: display on
+        0x30, 0x10,       // BR 10
+    ];
+
+    fn vip() -> Vip {
+        let mut vip = Vip::new(&INTERPRETER).unwrap();
+        // A checkerboard on the first row and a lit pixel in the bottom right corner
+        vip.bus.ram[0xF00..0xF08].fill(0xAA);
+        vip.bus.ram[0xFFF] = 0x01;
+        vip
+    }
+
+    #[test]
+    fn it_draws_the_display_buffer() {
+        let mut vip = vip();
+        let mut video = [1; LORES_WIDTH * LORES_HEIGHT];
+        let executed = vip.run_frame(&[0; 16], &mut video);
+        assert!(executed > 0);
+
+        assert_eq!(&video[..4], [0xFFFFFFFF, 0, 0xFFFFFFFF, 0]);
+        assert_eq!(video[LORES_WIDTH], 0);
+        assert_eq!(video[LORES_WIDTH * LORES_HEIGHT - 1], 0xFFFFFFFF);
+        assert_eq!(video.iter().filter(|pixel| **pixel != 0).count(), 33);
+
+        // The interrupt handler returned to the loop with the stack where it was
+        assert_eq!(vip.cpu.r[2], 0xECF);
+        assert!(vip.cpu.ie);
+        assert_eq!(vip.cpu.r[1], 0x8146);
+    }
+
+    #[test]
+    fn it_counts_down_the_timers() {
+        let mut vip = vip();
+        let mut video = [0; LORES_WIDTH * LORES_HEIGHT];
+        vip.run_frame(&[0; 16], &mut video);
+
+        vip.cpu.r[8] = 0x0502;
+        vip.run_frame(&[0; 16], &mut video);
+        assert_eq!(vip.cpu.r[8], 0x0401);
+        assert!(vip.sound());
+        assert!(matches!(vip.sound_changes(), [(_, true)]));
+
+        vip.run_frame(&[0; 16], &mut video);
+        assert_eq!(vip.cpu.r[8], 0x0300);
+        assert!(!vip.sound());
+    }
+
+    #[test]
+    fn it_reads_the_keypad() {
+        let mut vip = vip();
+        let mut keypad = [0; 16];
+        keypad[0xA] = 1;
+        vip.bus.keypad = keypad;
+
+        vip.bus.output(2, 0x0A);
+        assert!(vip.bus.flag(3));
+        vip.bus.output(2, 0x0B);
+        assert!(!vip.bus.flag(3));
+    }
+}
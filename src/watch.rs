@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Notices when a file is changed on disk by polling its modification time.
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_poll: Option<Instant>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        FileWatcher { modified: modified(&path), path, next_poll: None }
+    }
+
+    /// Whether the file has changed since the last poll. A file that's missing for a moment,
+    /// e.g. while an editor replaces it, isn't a change until it's back.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self.next_poll.map_or(false, |next_poll| now < next_poll) {
+            return false;
+        }
+        self.next_poll = Some(now + POLL_INTERVAL);
+
+        match modified(&self.path) {
+            Some(modified) if Some(modified) != self.modified => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As if the file was last seen long before it was modified, the file system's clock may not
+    /// have moved on between writes
+    fn forget_modified(watcher: &mut FileWatcher) {
+        watcher.modified = Some(SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn it_notices_changed_files() {
+        let path = std::env::temp_dir().join(format!("chip8-rs-watch-{}.ch8", std::process::id()));
+        fs::write(&path, [0x12, 0x00]).unwrap();
+
+        let mut watcher = FileWatcher::new(&path);
+        let now = Instant::now();
+        assert!(!watcher.poll(now));
+
+        // Not checked again until the poll interval has passed
+        forget_modified(&mut watcher);
+        assert!(!watcher.poll(now + POLL_INTERVAL / 2));
+        assert!(watcher.poll(now + POLL_INTERVAL));
+        assert!(!watcher.poll(now + POLL_INTERVAL * 2));
+
+        // Deleting the file isn't a change, bringing it back is
+        fs::remove_file(&path).unwrap();
+        forget_modified(&mut watcher);
+        assert!(!watcher.poll(now + POLL_INTERVAL * 3));
+        fs::write(&path, [0x12, 0x02]).unwrap();
+        assert!(watcher.poll(now + POLL_INTERVAL * 4));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::audio::{AudioSink, SoundEvent, Synth, Tone};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Somewhere to write audio samples to.
+pub trait SampleWriter {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Flush anything buffered, and fill in any header now the length is known
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl<S: SampleWriter + ?Sized> SampleWriter for Box<S> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        (**self).write_samples(samples)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Create a WAV file, or a file of raw signed 16 bit little endian samples if the name doesn't end in `.wav`
+pub fn create_file<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Box<dyn SampleWriter>> {
+    let path = path.as_ref();
+    let wav = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("wav"));
+
+    if wav {
+        Ok(Box::new(WavWriter::create(path, sample_rate)?))
+    } else {
+        Ok(Box::new(RawWriter::new(BufWriter::new(File::create(path)?))))
+    }
+}
+
+/// Writes mono 16 bit PCM WAV files. The sizes in the header are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;  // PCM
+        out.write_all(&1u16.to_le_bytes())?;  // mono
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?;  // bytes per second
+        out.write_all(&2u16.to_le_bytes())?;  // bytes per sample
+        out.write_all(&16u16.to_le_bytes())?;  // bits per sample
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> SampleWriter for WavWriter<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&to_i16(*sample).to_le_bytes())?;
+        }
+
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let data_size = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+/// Writes headerless signed 16 bit little endian samples.
+pub struct RawWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> RawWriter<W> {
+    pub fn new(out: W) -> Self {
+        RawWriter { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> SampleWriter for RawWriter<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&to_i16(*sample).to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Renders the buzzer and any digitised sound to a file in emulated time, one frame at a time, so the recording has
+/// exact timing however fast or slow the emulator actually ran.
+pub struct AudioRecorder<S: SampleWriter> {
+    writer: S,
+    synth: Synth,
+    sample_rate: u32,
+    frame_rate: u32,
+    frames: u64,
+    gate: bool,
+    muted: bool,
+    buffer: Vec<f32>,
+}
+
+impl<S: SampleWriter> AudioRecorder<S> {
+    pub fn new(writer: S, tone: Tone, sample_rate: u32, frame_rate: u32) -> Self {
+        AudioRecorder {
+            writer,
+            synth: Synth::new(tone, sample_rate as i32),
+            sample_rate,
+            frame_rate,
+            frames: 0,
+            gate: false,
+            muted: false,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Record a frame of audio, switching the buzzer on and off as the frame's sound events say
+    /// and mixing in the frame's digitised sound
+    pub fn record_frame(&mut self, events: &[SoundEvent], samples: &[f32]) -> io::Result<()> {
+        // Round each frame boundary to the nearest sample so the length never drifts
+        let sample_at = |frame: u64| frame * self.sample_rate as u64 / self.frame_rate as u64;
+        let length = (sample_at(self.frames + 1) - sample_at(self.frames)) as usize;
+
+        let mut events = events.iter().peekable();
+        self.buffer.clear();
+        for i in 0..length {
+            while let Some(event) = events.next_if(|e| e.offset * length as f32 <= i as f32) {
+                self.gate = event.on;
+            }
+            let sample = if self.muted { 0.0 } else { samples.get(i).copied().unwrap_or(0.0) };
+            self.buffer.push(self.synth.next_sample(self.gate && !self.muted) + sample);
+        }
+
+        // Anything left happened at the very end of the frame
+        if let Some(event) = events.last() {
+            self.gate = event.on;
+        }
+
+        self.frames += 1;
+        self.writer.write_samples(&self.buffer)
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.finish()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.writer
+    }
+}
+
+/// Recording to a file can stand in for an audio device
+impl<S: SampleWriter> AudioSink for AudioRecorder<S> {
+    fn set_tone(&mut self, tone: Tone) {
+        self.synth.set_tone(tone);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    fn play(&mut self, events: &[SoundEvent], samples: &[f32]) -> io::Result<()> {
+        self.record_frame(events, samples)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        AudioRecorder::finish(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn sample(wav: &[u8], i: usize) -> i16 {
+        let at = HEADER_SIZE as usize + i * 2;
+        i16::from_le_bytes([wav[at], wav[at + 1]])
+    }
+
+    #[test]
+    fn it_writes_wav_files() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        writer.finish().unwrap();
+        let wav = writer.into_inner().into_inner();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], 44u32.to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], 8u32.to_le_bytes());
+        assert_eq!([sample(&wav, 0), sample(&wav, 1), sample(&wav, 2), sample(&wav, 3)], [0, 32767, -32767, 32767]);
+    }
+
+    #[test]
+    fn it_writes_raw_samples() {
+        let mut writer = RawWriter::new(Vec::new());
+        writer.write_samples(&[0.5, -1.0]).unwrap();
+        assert_eq!(writer.into_inner(), [0xFF, 0x3F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn it_records_sound_events_in_emulated_time() {
+        let writer = WavWriter::new(Cursor::new(Vec::new()), 6000).unwrap();
+        let mut recorder = AudioRecorder::new(writer, Tone::default(), 6000, 60);
+
+        recorder.record_frame(&[], &[]).unwrap();
+        recorder.record_frame(&[SoundEvent { frame: 1, offset: 0.5, on: true }], &[]).unwrap();
+        recorder.record_frame(&[SoundEvent { frame: 2, offset: 1.0, on: false }], &[]).unwrap();
+        recorder.record_frame(&[], &[]).unwrap();
+        recorder.finish().unwrap();
+        let wav = recorder.into_inner().into_inner().into_inner();
+
+        // 100 samples per frame, sounding from halfway through the second frame to the end of the third
+        assert_eq!(wav.len(), 44 + 400 * 2);
+        assert!((0..150).all(|i| sample(&wav, i) == 0));
+        assert_ne!(sample(&wav, 151), 0);
+        assert_ne!(sample(&wav, 299), 0);
+
+        // Followed by the release of the envelope
+        assert!((330..400).all(|i| sample(&wav, i) == 0));
+    }
+
+    #[test]
+    fn it_records_digitised_sound() {
+        let writer = WavWriter::new(Cursor::new(Vec::new()), 6000).unwrap();
+        let mut recorder = AudioRecorder::new(writer, Tone::default(), 6000, 60);
+
+        recorder.record_frame(&[], &[0.5; 60]).unwrap();
+        recorder.set_muted(true);
+        recorder.record_frame(&[], &[0.5; 100]).unwrap();
+        recorder.finish().unwrap();
+        let wav = recorder.into_inner().into_inner().into_inner();
+
+        // A short frame of samples is padded with silence, and muting silences them too
+        assert_eq!(wav.len(), 44 + 200 * 2);
+        assert!((0..60).all(|i| sample(&wav, i) == 16383));
+        assert!((60..200).all(|i| sample(&wav, i) == 0));
+    }
+}
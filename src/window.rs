@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sdl2::rect::Rect;
+use serde::{Deserialize, Serialize};
+
+/// Where the window was and how big it was when the emulator last closed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowState {
+    /// `$XDG_CONFIG_HOME/chip8-rs/window.toml` or the platform equivalent
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8-rs").join("window.toml"))
+    }
+
+    pub fn parse(contents: &str) -> Result<WindowState, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    /// Load the saved state, if there is any. A file that can't be read is ignored as it's only a convenience.
+    pub fn load(path: &Path) -> Option<WindowState> {
+        fs::read_to_string(path).ok().and_then(|contents| WindowState::parse(&contents).ok())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("could not create '{}': {}", dir.display(), e))?;
+        }
+        fs::write(path, contents).map_err(|e| format!("could not write '{}': {}", path.display(), e))
+    }
+}
+
+/// The largest area of the window the display fits in without changing its aspect ratio, centred
+/// with black bars either side. With integer scaling every CHIP-8 pixel is the same size on screen.
+pub fn viewport(window_width: u32, window_height: u32, width: u32, height: u32, integer_scaling: bool) -> Rect {
+    let scale = (window_width as f32 / width as f32).min(window_height as f32 / height as f32);
+    let scale = if integer_scaling { scale.floor().max(1.0) } else { scale };
+
+    let w = (width as f32 * scale).round() as u32;
+    let h = (height as f32 * scale).round() as u32;
+    let x = (window_width as i32 - w as i32) / 2;
+    let y = (window_height as i32 - h as i32) / 2;
+
+    Rect::new(x, y, w, h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_letterboxes_the_display() {
+        // Exact fit
+        assert_eq!(viewport(640, 320, 64, 32, false), Rect::new(0, 0, 640, 320));
+
+        // Bars at the top and bottom, then at the sides
+        assert_eq!(viewport(640, 480, 64, 32, false), Rect::new(0, 80, 640, 320));
+        assert_eq!(viewport(1000, 320, 64, 32, false), Rect::new(180, 0, 640, 320));
+    }
+
+    #[test]
+    fn it_scales_by_whole_pixels() {
+        assert_eq!(viewport(700, 400, 64, 32, false), Rect::new(0, 25, 700, 350));
+        assert_eq!(viewport(700, 400, 64, 32, true), Rect::new(30, 40, 640, 320));
+
+        // Never smaller than one pixel per pixel
+        assert_eq!(viewport(32, 16, 64, 32, true), Rect::new(-16, -8, 64, 32));
+    }
+
+    #[test]
+    fn it_round_trips_window_state() {
+        let state = WindowState { x: 10, y: -20, width: 800, height: 600 };
+        assert_eq!(WindowState::parse(&toml::to_string(&state).unwrap()), Ok(state));
+        assert!(WindowState::parse("x = 1").is_err());
+    }
+}